@@ -0,0 +1,178 @@
+use crate::error::{Error, Result};
+use crate::object::ObjectId;
+use crate::pack;
+use crate::repository::{CheckoutOptions, Repository};
+use std::fs;
+use std::path::Path;
+
+// Helper function to parse default branch from refs response
+fn parse_default_branch(refs: &str) -> Option<String> {
+    for line in refs.lines() {
+        if line.contains("symref=HEAD:") {
+            if let Some(start) = line.find("symref=HEAD:") {
+                let symref_part = &line[start + 12..]; // Skip "symref=HEAD:"
+                if let Some(end) = symref_part.find(' ') {
+                    let branch_ref = &symref_part[..end];
+                    if let Some(branch_name) = branch_ref.strip_prefix("refs/heads/") {
+                        return Some(branch_name.to_string());
+                    }
+                } else {
+                    // If there's no space, take the rest of the line
+                    if let Some(branch_name) = symref_part.strip_prefix("refs/heads/") {
+                        return Some(branch_name.to_string());
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
+// Helper function to get head commit from refs response
+fn get_head_commit(refs: &str) -> Option<ObjectId> {
+    // Look for refs/heads/master or refs/heads/main line
+    for line in refs.lines() {
+        if line.ends_with("refs/heads/master") || line.ends_with("refs/heads/main") {
+            // Line format: "003f47b37f1a82bfe85f6d8df52b6258b75e4343b7fd refs/heads/master"
+            // Skip the length prefix (4 hex chars) and extract the SHA
+            if line.len() >= 48 {
+                // 4 (length) + 40 (SHA) + 4 (space + "refs")
+                let sha_part = line.get(4..44)?; // Extract exactly 40 characters after length prefix
+                if let Ok(id) = ObjectId::from_hex(sha_part) {
+                    return Some(id);
+                }
+            }
+        }
+    }
+
+    None
+}
+
+// Helper function to create the default branch at the cloned commit and point HEAD at it
+fn write_branch_refs(
+    repo: &Repository,
+    branch: &str,
+    head_commit: &ObjectId,
+    repository_url: &str,
+) -> Result<()> {
+    let branch_ref = format!("refs/heads/{}", branch);
+    repo.write_symbolic_ref("HEAD", &branch_ref)?;
+    repo.update_ref(
+        &branch_ref,
+        head_commit,
+        None,
+        &format!("clone: from {}", repository_url),
+    )
+}
+
+// Main clone function using improved logic
+pub fn clone_repository(repository_url: &str, local_path: impl AsRef<Path>) -> Result<Repository> {
+    let parent = local_path.as_ref();
+
+    // Create target directory structure
+    if let Some(parent_dir) = parent.parent() {
+        fs::create_dir_all(parent_dir)?;
+    }
+    fs::create_dir_all(parent)?;
+
+    let repo = Repository::init(parent)?;
+
+    let client = reqwest::blocking::Client::new();
+
+    // Fetch refs with improved error handling
+    let smart_url = format!("{}/info/refs?service=git-upload-pack", repository_url);
+
+    let refs_response = client
+        .get(&smart_url)
+        .send()
+        .map_err(|e| Error::Transport(e.to_string()))?;
+
+    if !refs_response.status().is_success() {
+        return Err(Error::Transport(format!(
+            "Failed to get refs: {}",
+            refs_response.status()
+        )));
+    }
+
+    let refs_bytes = refs_response
+        .bytes()
+        .map_err(|e| Error::Transport(e.to_string()))?;
+
+    let refs_data = String::from_utf8_lossy(&refs_bytes);
+
+    let head_commit = get_head_commit(&refs_data)
+        .ok_or_else(|| Error::Protocol("No head commit found!".to_string()))?;
+
+    // Parse the default branch from symbolic ref
+    let default_branch = parse_default_branch(&refs_data).unwrap_or_else(|| "main".to_string());
+
+    // Create pack request
+    let pack_url = format!("{}/git-upload-pack", repository_url);
+    // Advertise ofs-delta so the server may send offset deltas as-is
+    let want_line = format!("want {} ofs-delta\n", head_commit);
+    let pack_request = format!(
+        "{:04x}{}0000\
+         0009done\n",
+        want_line.len() + 4,
+        want_line
+    );
+
+    let pack_response = client
+        .post(&pack_url)
+        .header("Content-Type", "application/x-git-upload-pack-request")
+        .header("Accept", "application/x-git-upload-pack-result")
+        .body(pack_request)
+        .send()
+        .map_err(|e| Error::Transport(e.to_string()))?;
+
+    if !pack_response.status().is_success() {
+        return Err(Error::Transport(format!(
+            "Failed to get packfile: {}",
+            pack_response.status()
+        )));
+    }
+
+    let pack_data = pack_response
+        .bytes()
+        .map_err(|e| Error::Transport(e.to_string()))?;
+
+    // An empty response still leaves a repository with its refs
+    if pack_data.is_empty() {
+        // Write HEAD and refs using correct branch
+        write_branch_refs(&repo, &default_branch, &head_commit, repository_url)?;
+
+        return Ok(repo);
+    }
+
+    // Find packfile start
+    let mut pack_start = 0;
+    for (i, chunk) in pack_data.windows(4).enumerate() {
+        if chunk == b"PACK" {
+            pack_start = i;
+            break;
+        }
+    }
+
+    if pack_start == 0 && !pack_data.starts_with(b"PACK") {
+        return Err(Error::Protocol(
+            "Could not find packfile in response".to_string(),
+        ));
+    }
+
+    pack::index_pack(
+        repo.odb(),
+        &repo.git_dir().join("objects/pack"),
+        &pack_data[pack_start..],
+    )?;
+
+    // Write HEAD and refs using correct branch
+    write_branch_refs(&repo, &default_branch, &head_commit, repository_url)?;
+
+    // Create working directory from HEAD commit
+    let options = CheckoutOptions {
+        warn_unsupported: true,
+    };
+    repo.create_working_directory_from_commit(&head_commit, &options)?;
+
+    Ok(repo)
+}
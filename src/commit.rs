@@ -0,0 +1,123 @@
+use crate::error::{Error, Result};
+use crate::ident::Signature;
+use crate::object::ObjectId;
+use crate::repository::Repository;
+
+// What commit does beyond recording the index on top of HEAD
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CommitOptions {
+    // Replace the commit at HEAD, keeping its parents and author (--amend)
+    pub amend: bool,
+    // Record the commit even if it changes nothing (--allow-empty)
+    pub allow_empty: bool,
+}
+
+// The commit that was made
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommitSummary {
+    pub id: ObjectId,
+    // The branch that moved, or None when HEAD is detached
+    pub branch: Option<String>,
+    // Whether the commit has no parents
+    pub root: bool,
+    // The first line of the message
+    pub subject: String,
+    pub author: Signature,
+    pub committer: Signature,
+}
+
+// Tidy a message the way git does for -m and -F: trailing whitespace goes,
+// runs of blank lines become one, and blank lines at either end are dropped.
+// Messages are bytes in whatever encoding the user wrote them in
+pub fn cleanup_message(message: &[u8]) -> Vec<u8> {
+    let mut cleaned = Vec::new();
+    let mut pending_blank = false;
+    for line in message.split(|&b| b == b'\n').map(<[u8]>::trim_ascii_end) {
+        if line.is_empty() {
+            pending_blank = !cleaned.is_empty();
+            continue;
+        }
+        if pending_blank {
+            cleaned.push(b'\n');
+            pending_blank = false;
+        }
+        cleaned.extend_from_slice(line);
+        cleaned.push(b'\n');
+    }
+    cleaned
+}
+
+impl Repository {
+    // Helper function for commit command: record the index as a commit on top of
+    // HEAD, or in place of it when amending, and move the current branch there
+    pub fn commit(&self, message: Option<&[u8]>, options: &CommitOptions) -> Result<CommitSummary> {
+        let head_id = self.read_ref("HEAD")?;
+        let head = match head_id {
+            Some(id) => Some(self.read_commit(&id)?),
+            None if options.amend => return Err(Error::NothingToAmend),
+            None => None,
+        };
+
+        // Amending rewrites HEAD in place, so the new commit takes over its parents
+        let (parents, author) = match (&head, options.amend) {
+            (Some(head), true) => (head.parents.clone(), Some(head.author.as_slice())),
+            _ => (head_id.into_iter().collect(), None),
+        };
+
+        let message = match (message, &head) {
+            (Some(message), _) => cleanup_message(message),
+            (None, Some(head)) if options.amend => head.message.clone(),
+            (None, _) => Vec::new(),
+        };
+        if message.is_empty() {
+            return Err(Error::EmptyCommitMessage);
+        }
+
+        let tree = self.write_tree(None, false)?;
+        let parent_tree = match parents.first() {
+            Some(parent) => Some(self.read_commit(parent)?.tree),
+            None => None,
+        };
+        // An empty index is nothing to commit either, even for a first commit
+        let unchanged = match parent_tree {
+            Some(parent_tree) => parent_tree == tree && parents.len() == 1,
+            None => self.read_index()?.entries.is_empty(),
+        };
+        if unchanged && !options.allow_empty && !options.amend {
+            return Err(Error::NothingToCommit);
+        }
+
+        let id = self.write_commit(&tree, &parents, author, &message, &[], None)?;
+
+        // Messages need not be UTF-8, so the subject is only decoded for display
+        let first_line = message.split(|&b| b == b'\n').next().unwrap_or_default();
+        let subject = String::from_utf8_lossy(first_line).into_owned();
+        let written = self.read_commit(&id)?;
+        let signature = |value: &[u8]| {
+            let value = String::from_utf8_lossy(value);
+            Signature::parse(&value)
+                .ok_or_else(|| Error::CorruptObject(format!("bad ident {}", value)))
+        };
+        let (author, committer) = (signature(&written.author)?, signature(&written.committer)?);
+        let reflog_kind = match (options.amend, parents.is_empty()) {
+            (true, _) => "commit (amend)",
+            (false, true) => "commit (initial)",
+            (false, false) => "commit",
+        };
+        self.update_ref(
+            "HEAD",
+            &id,
+            head_id.as_ref(),
+            &format!("{}: {}", reflog_kind, subject.trim_start()),
+        )?;
+
+        Ok(CommitSummary {
+            id,
+            branch: self.head_branch()?,
+            root: parents.is_empty(),
+            subject,
+            author,
+            committer,
+        })
+    }
+}
@@ -0,0 +1,1059 @@
+use crate::error::{Error, Result};
+use crate::ignore::{expand_home, wildmatch};
+use crate::repository::{write_locked, xdg_config_home, Repository};
+use std::env;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+// How deeply include.path may nest before we assume a cycle
+pub(crate) const MAX_INCLUDE_DEPTH: usize = 10;
+
+// Helper function to check a section name: letters, digits, '-' and '.'
+fn is_section_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.')
+}
+
+// Helper function to check a variable name: a letter, then letters, digits and '-'
+fn is_key_name(name: &str) -> bool {
+    name.bytes().next().is_some_and(|b| b.is_ascii_alphabetic())
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+}
+
+// Split "section[.subsection].key" into its canonical section ("core",
+// "remote.origin") and lowercased key, and the section and subsection as written
+pub fn parse_name(name: &str) -> Result<(String, String)> {
+    let (Some(first_dot), Some(last_dot)) = (name.find('.'), name.rfind('.')) else {
+        return Err(Error::InvalidConfigKey(format!(
+            "key does not contain a section: {}",
+            name
+        )));
+    };
+    let key = &name[last_dot + 1..];
+    if key.is_empty() {
+        return Err(Error::InvalidConfigKey(format!(
+            "key does not contain variable name: {}",
+            name
+        )));
+    }
+
+    let section = &name[..first_dot];
+    if !is_key_name(key) || !is_section_name(section) {
+        return Err(Error::InvalidConfigKey(format!("invalid key: {}", name)));
+    }
+
+    // Subsections keep their case; section and key names do not
+    let mut canonical = section.to_ascii_lowercase();
+    if first_dot != last_dot {
+        canonical.push_str(&name[first_dot..last_dot]);
+    }
+    Ok((canonical, key.to_ascii_lowercase()))
+}
+
+// Which kind of file a setting came from, lowest precedence first
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConfigScope {
+    System,
+    Global,
+    Local,
+    // A file named with --file
+    File,
+}
+
+// One "key = value" line of a config file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigEntry {
+    // Lowercased section, then the subsection as written: "remote.origin"
+    pub section: String,
+    // Lowercased variable name
+    pub key: String,
+    // None for a bare "key" line, which means true
+    pub value: Option<String>,
+    pub scope: ConfigScope,
+    // The file it was read from, as --show-origin prints it
+    pub origin: String,
+    // Bytes of the line within its file, including the newline
+    span: Range<usize>,
+}
+
+impl ConfigEntry {
+    // The full name as --list prints it: "remote.origin.url"
+    pub fn name(&self) -> String {
+        format!("{}.{}", self.section, self.key)
+    }
+}
+
+// A "[section]" header and the lines up to the next one
+#[derive(Clone, Debug)]
+struct ConfigSection {
+    name: String,
+    header: Range<usize>,
+    end: usize,
+}
+
+// Tracks the position of the parser within one config file
+struct ConfigParser<'a> {
+    content: &'a [u8],
+    pos: usize,
+    line: usize,
+    origin: &'a str,
+}
+
+impl ConfigParser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.content.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<u8> {
+        let b = self.peek()?;
+        self.pos += 1;
+        if b == b'\n' {
+            self.line += 1;
+        }
+        Some(b)
+    }
+
+    fn error(&self) -> Error {
+        // A line is to blame for its own newline
+        let ends_line = self.pos > 0 && self.content[self.pos - 1] == b'\n';
+        Error::BadConfigLine {
+            line: self.line - ends_line as usize,
+            origin: self.origin.to_string(),
+        }
+    }
+
+    fn skip_blanks(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(b) = self.bump() {
+            if b == b'\n' {
+                break;
+            }
+        }
+    }
+
+    // Parse a header after its '[': returns the canonical section name
+    fn section_header(&mut self) -> Result<String> {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'.')
+        {
+            self.pos += 1;
+        }
+        let name = String::from_utf8_lossy(&self.content[start..self.pos]).to_ascii_lowercase();
+        if name.is_empty() {
+            return Err(self.error());
+        }
+
+        match self.bump() {
+            Some(b']') => Ok(name),
+            Some(b' ' | b'\t') => {
+                // The quoted subsection of [section "subsection"]
+                self.skip_blanks();
+                if self.bump() != Some(b'"') || name.contains('.') {
+                    return Err(self.error());
+                }
+                let mut subsection = Vec::new();
+                loop {
+                    match self.bump() {
+                        Some(b'"') => break,
+                        Some(b'\\') => match self.bump() {
+                            Some(b'\n') | None => return Err(self.error()),
+                            Some(b) => subsection.push(b),
+                        },
+                        Some(b'\n') | None => return Err(self.error()),
+                        Some(b) => subsection.push(b),
+                    }
+                }
+                if self.bump() != Some(b']') {
+                    return Err(self.error());
+                }
+                Ok(format!("{}.{}", name, String::from_utf8_lossy(&subsection)))
+            }
+            _ => Err(self.error()),
+        }
+    }
+
+    // Parse a value after its '=', up to the end of the line: quotes are
+    // removed, escapes decoded and whitespace outside quotes squeezed
+    fn value(&mut self) -> Result<String> {
+        let mut value = Vec::new();
+        let mut quoted = false;
+        let mut pending_spaces = 0;
+        self.skip_blanks();
+        loop {
+            let Some(b) = self.bump() else {
+                if quoted {
+                    return Err(self.error());
+                }
+                break;
+            };
+            match b {
+                b'\n' if quoted => return Err(self.error()),
+                b'\n' => break,
+                b';' | b'#' if !quoted => {
+                    self.skip_line();
+                    break;
+                }
+                b' ' | b'\t' | b'\r' if !quoted => {
+                    if !value.is_empty() {
+                        pending_spaces += 1;
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+
+            value.extend(std::iter::repeat(b' ').take(pending_spaces));
+            pending_spaces = 0;
+            match b {
+                b'"' => quoted = !quoted,
+                b'\\' => match self.bump() {
+                    // A backslash at the end of a line continues the value
+                    Some(b'\n') => {}
+                    Some(b't') => value.push(b'\t'),
+                    Some(b'b') => value.push(0x08),
+                    Some(b'n') => value.push(b'\n'),
+                    Some(escaped @ (b'\\' | b'"')) => value.push(escaped),
+                    _ => return Err(self.error()),
+                },
+                b => value.push(b),
+            }
+        }
+        Ok(String::from_utf8_lossy(&value).into_owned())
+    }
+}
+
+// One config file, parsed but kept as text so edits leave the rest of it untouched
+#[derive(Clone, Debug)]
+pub struct ConfigFile {
+    path: PathBuf,
+    content: String,
+    entries: Vec<ConfigEntry>,
+    sections: Vec<ConfigSection>,
+}
+
+impl ConfigFile {
+    // Parse config text; `origin` names the file in messages and --show-origin
+    pub fn parse(
+        path: impl Into<PathBuf>,
+        content: String,
+        scope: ConfigScope,
+        origin: &str,
+    ) -> Result<Self> {
+        let mut entries = Vec::new();
+        let mut sections: Vec<ConfigSection> = Vec::new();
+        let mut parser = ConfigParser {
+            content: content.as_bytes(),
+            pos: 0,
+            line: 1,
+            origin,
+        };
+
+        // A UTF-8 byte order mark is allowed at the start
+        if parser.content.starts_with(b"\xef\xbb\xbf") {
+            parser.pos = 3;
+        }
+
+        loop {
+            let line_start = parser.pos;
+            parser.skip_blanks();
+            let Some(b) = parser.peek() else {
+                break;
+            };
+            match b {
+                b'\n' => {
+                    parser.bump();
+                }
+                b'#' | b';' => parser.skip_line(),
+                b'[' => {
+                    parser.bump();
+                    let name = parser.section_header()?;
+                    if let Some(previous) = sections.last_mut() {
+                        previous.end = line_start;
+                    }
+                    sections.push(ConfigSection {
+                        name,
+                        header: line_start..parser.pos,
+                        end: content.len(),
+                    });
+                }
+                b if b.is_ascii_alphabetic() => {
+                    let Some(section) = sections.last() else {
+                        // Blame the key's own line, not the newline before it
+                        parser.bump();
+                        return Err(parser.error());
+                    };
+                    let key_start = parser.pos;
+                    while parser
+                        .peek()
+                        .is_some_and(|b| b.is_ascii_alphanumeric() || b == b'-')
+                    {
+                        parser.pos += 1;
+                    }
+                    let key = content[key_start..parser.pos].to_ascii_lowercase();
+                    parser.skip_blanks();
+
+                    let value = match parser.peek() {
+                        Some(b'=') => {
+                            parser.bump();
+                            Some(parser.value()?)
+                        }
+                        Some(b'\n') => {
+                            parser.bump();
+                            None
+                        }
+                        Some(b'#' | b';') => {
+                            parser.skip_line();
+                            None
+                        }
+                        None => None,
+                        Some(_) => return Err(parser.error()),
+                    };
+
+                    entries.push(ConfigEntry {
+                        section: section.name.clone(),
+                        key,
+                        value,
+                        scope,
+                        origin: origin.to_string(),
+                        span: line_start..parser.pos,
+                    });
+                }
+                _ => {
+                    parser.bump();
+                    return Err(parser.error());
+                }
+            }
+        }
+
+        Ok(Self {
+            path: path.into(),
+            content,
+            entries,
+            sections,
+        })
+    }
+
+    // Read a config file; a missing one is empty
+    pub fn read(path: &Path, scope: ConfigScope, origin: &str) -> Result<Self> {
+        let content = match fs::read(path) {
+            Ok(content) => String::from_utf8_lossy(&content).into_owned(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Self::parse(path, content, scope, origin)
+    }
+
+    pub fn entries(&self) -> &[ConfigEntry] {
+        &self.entries
+    }
+
+    // Helper function to find the entries for a canonical section and key
+    fn matching(&self, section: &str, key: &str) -> Vec<usize> {
+        (0..self.entries.len())
+            .filter(|&i| self.entries[i].section == section && self.entries[i].key == key)
+            .collect()
+    }
+
+    // Set `name` to `value`, replacing its one existing value unless `add` asks
+    // for another; several existing values are refused as ambiguous
+    pub fn set(&mut self, name: &str, value: &str, add: bool) -> Result<()> {
+        let (section, key) = parse_name(name)?;
+        let existing = self.matching(&section, &key);
+        let written_key = &name[name.rfind('.').map_or(0, |dot| dot + 1)..];
+        let line = format!("\t{} = {}\n", written_key, format_value(value));
+
+        if !add {
+            match existing.as_slice() {
+                [] => {}
+                [only] => {
+                    let span = self.entries[*only].span.clone();
+                    let line = match self.content[..span.start].ends_with('\n') || span.start == 0 {
+                        true => line,
+                        false => format!("\n{}", line),
+                    };
+                    self.content.replace_range(span, &line);
+                    return self.reparse();
+                }
+                _ => return Err(Error::ConfigMultipleValues(name.to_string())),
+            }
+        }
+
+        // New values go after the last line of the last matching section
+        let target = self.sections.iter().rev().find(|s| s.name == section);
+        match target {
+            Some(target) => {
+                let insert_at = self
+                    .entries
+                    .iter()
+                    .filter(|entry| {
+                        entry.span.start >= target.header.start && entry.span.end <= target.end
+                    })
+                    .map(|entry| entry.span.end)
+                    .max()
+                    .unwrap_or(target.header.end);
+                let mut text = line;
+                if !self.content[..insert_at].ends_with('\n') {
+                    text.insert(0, '\n');
+                }
+                self.content.insert_str(insert_at, &text);
+            }
+            None => {
+                if !self.content.is_empty() && !self.content.ends_with('\n') {
+                    self.content.push('\n');
+                }
+                self.content.push_str(&format_header(name));
+                self.content.push_str(&line);
+            }
+        }
+        self.reparse()
+    }
+
+    // Remove `name`; unless `all`, it must have exactly one value. Returns how
+    // many lines went, and drops sections left with nothing in them.
+    pub fn unset(&mut self, name: &str, all: bool) -> Result<usize> {
+        let (section, key) = parse_name(name)?;
+        let existing = self.matching(&section, &key);
+        if existing.len() > 1 && !all {
+            return Err(Error::ConfigMultipleValues(name.to_string()));
+        }
+
+        // Work backwards so earlier spans stay valid
+        let mut removals: Vec<Range<usize>> = existing
+            .iter()
+            .map(|&i| self.entries[i].span.clone())
+            .collect();
+        for section in &self.sections {
+            let inside: Vec<&Range<usize>> = removals
+                .iter()
+                .filter(|span| span.start >= section.header.end && span.end <= section.end)
+                .collect();
+            if inside.is_empty() {
+                continue;
+            }
+            let mut rest = self.content[section.header.end..section.end].to_string();
+            for span in inside.iter().rev() {
+                rest.replace_range(
+                    span.start - section.header.end..span.end - section.header.end,
+                    "",
+                );
+            }
+            if rest.trim().is_empty() {
+                removals.push(section.header.start..section.end);
+            }
+        }
+        removals.sort_by_key(|span| (span.start, std::cmp::Reverse(span.end)));
+
+        // Spans nested in a removed section are dropped with it
+        let mut merged: Vec<Range<usize>> = Vec::new();
+        for span in removals {
+            match merged.last() {
+                Some(last) if span.start < last.end => {}
+                _ => merged.push(span),
+            }
+        }
+        for span in merged.iter().rev() {
+            self.content.replace_range(span.clone(), "");
+        }
+
+        self.reparse()?;
+        Ok(existing.len())
+    }
+
+    // Helper function to refresh entries and sections after editing the text
+    fn reparse(&mut self) -> Result<()> {
+        let origin = self.entries.first().map_or_else(
+            || self.path.display().to_string(),
+            |entry| entry.origin.clone(),
+        );
+        let scope = self
+            .entries
+            .first()
+            .map_or(ConfigScope::File, |entry| entry.scope);
+        *self = Self::parse(
+            self.path.clone(),
+            std::mem::take(&mut self.content),
+            scope,
+            &origin,
+        )?;
+        Ok(())
+    }
+
+    // Write the file back through a lock file
+    pub fn write(&self) -> Result<()> {
+        write_locked(&self.path, self.content.as_bytes())
+    }
+}
+
+// Helper function to write a value so it reads back unchanged: quoted when
+// it has edge spaces or comment characters, with escapes for the rest
+fn format_value(value: &str) -> String {
+    let needs_quotes = value.starts_with(' ') || value.ends_with(' ') || value.contains([';', '#']);
+    let mut formatted = String::new();
+    if needs_quotes {
+        formatted.push('"');
+    }
+    for c in value.chars() {
+        match c {
+            '\n' => formatted.push_str("\\n"),
+            '\t' => formatted.push_str("\\t"),
+            '"' => formatted.push_str("\\\""),
+            '\\' => formatted.push_str("\\\\"),
+            c => formatted.push(c),
+        }
+    }
+    if needs_quotes {
+        formatted.push('"');
+    }
+    formatted
+}
+
+// Helper function to write the header of a new section for `name`, keeping the
+// section name and any subsection as the user spelled them
+fn format_header(name: &str) -> String {
+    let first_dot = name.find('.').unwrap_or(name.len());
+    let last_dot = name.rfind('.').unwrap_or(name.len());
+    let section = &name[..first_dot];
+    if first_dot == last_dot {
+        return format!("[{}]\n", section);
+    }
+    let subsection = name[first_dot + 1..last_dot]
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"");
+    format!("[{} \"{}\"]\n", section, subsection)
+}
+
+// Every setting that applies, in precedence order: system, global, then the
+// repository, with included files spliced in where they are included
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    entries: Vec<ConfigEntry>,
+}
+
+impl Config {
+    // The system-wide file. GIT_CONFIG_NOSYSTEM only keeps it out of the usual
+    // lookup; --system still names it.
+    pub fn system_path() -> PathBuf {
+        env::var_os("GIT_CONFIG_SYSTEM")
+            .map_or_else(|| PathBuf::from("/etc/gitconfig"), PathBuf::from)
+    }
+
+    // The per-user files, lowest precedence first: the XDG file, then ~/.gitconfig
+    pub fn global_paths() -> Vec<PathBuf> {
+        if let Some(global) = env::var_os("GIT_CONFIG_GLOBAL") {
+            return vec![PathBuf::from(global)];
+        }
+        let xdg = xdg_config_home().map(|xdg| xdg.join("git/config"));
+        let home = env::var_os("HOME").map(|home| PathBuf::from(home).join(".gitconfig"));
+        xdg.into_iter().chain(home).collect()
+    }
+
+    // The per-user file that `config --global` writes: ~/.gitconfig, unless only
+    // the XDG file exists
+    pub fn global_write_path() -> Option<PathBuf> {
+        let paths = Self::global_paths();
+        match paths.as_slice() {
+            [xdg, home] if !home.exists() && xdg.exists() => Some(xdg.clone()),
+            _ => paths.last().cloned(),
+        }
+    }
+
+    // The repository's own config file and how --show-origin names it: from
+    // the top of the work tree when the repository has one
+    pub fn local_path(git_dir: &Path) -> (PathBuf, String) {
+        let local = git_dir.join("config");
+        let origin = match git_dir.file_name() == Some(".git".as_ref()) {
+            true => ".git/config".to_string(),
+            false => local.display().to_string(),
+        };
+        (local, origin)
+    }
+
+    // Every file that applies, lowest precedence first, with its scope and the
+    // name --show-origin gives it
+    pub fn files(git_dir: Option<&Path>) -> Vec<(PathBuf, ConfigScope, String)> {
+        let mut files = Vec::new();
+        if env::var_os("GIT_CONFIG_NOSYSTEM").is_none() {
+            let system = Self::system_path();
+            let origin = system.display().to_string();
+            files.push((system, ConfigScope::System, origin));
+        }
+        for global in Self::global_paths() {
+            let origin = global.display().to_string();
+            files.push((global, ConfigScope::Global, origin));
+        }
+        if let Some(git_dir) = git_dir {
+            let (local, origin) = Self::local_path(git_dir);
+            files.push((local, ConfigScope::Local, origin));
+        }
+        files
+    }
+
+    // Load every level; `git_dir` adds the repository's own config and lets
+    // includeIf "gitdir:" conditions match
+    pub fn load(git_dir: Option<&Path>) -> Result<Self> {
+        Self::load_files(&Self::files(git_dir), git_dir, true)
+    }
+
+    // Load the given files in order, and what they include when `includes` is set
+    pub fn load_files(
+        files: &[(PathBuf, ConfigScope, String)],
+        git_dir: Option<&Path>,
+        includes: bool,
+    ) -> Result<Self> {
+        let mut config = Self::default();
+        for (path, scope, origin) in files {
+            match includes {
+                true => config.include(path, *scope, origin, git_dir, 0)?,
+                false => config
+                    .entries
+                    .extend(ConfigFile::read(path, *scope, origin)?.entries),
+            }
+        }
+        Ok(config)
+    }
+
+    // Helper function to add a file's entries, following include.path and
+    // matching includeIf.<condition>.path settings
+    fn include(
+        &mut self,
+        path: &Path,
+        scope: ConfigScope,
+        origin: &str,
+        git_dir: Option<&Path>,
+        depth: usize,
+    ) -> Result<()> {
+        let file = ConfigFile::read(path, scope, origin)?;
+        let origin_dir = Path::new(origin).parent().unwrap_or(Path::new(""));
+        let file_dir = path.parent().unwrap_or(Path::new(""));
+
+        for entry in file.entries {
+            let included = match (entry.section.as_str(), entry.key.as_str(), &entry.value) {
+                ("include", "path", Some(target)) => Some(target.clone()),
+                (section, "path", Some(target)) => section
+                    .strip_prefix("includeif.")
+                    .filter(|condition| include_condition(condition, file_dir, git_dir))
+                    .map(|_| target.clone()),
+                _ => None,
+            };
+            self.entries.push(entry);
+
+            if let Some(target) = included {
+                let expanded = expand_home(&target);
+                let (target_path, target_origin) = match expanded.is_absolute() {
+                    true => (expanded.clone(), expanded),
+                    false => (file_dir.join(&expanded), origin_dir.join(&expanded)),
+                };
+                let target_origin = target_origin.display().to_string();
+                if !target_path.exists() {
+                    continue;
+                }
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(Error::ConfigIncludeDepth {
+                        path: target_path.display().to_string(),
+                        from: path.display().to_string(),
+                    });
+                }
+                self.include(&target_path, scope, &target_origin, git_dir, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[ConfigEntry] {
+        &self.entries
+    }
+
+    // Every value of `name`, lowest precedence first
+    pub fn get_all(&self, name: &str) -> Result<Vec<&ConfigEntry>> {
+        let (section, key) = parse_name(name)?;
+        Ok(self
+            .entries
+            .iter()
+            .filter(|entry| entry.section == section && entry.key == key)
+            .collect())
+    }
+
+    // The value that wins for `name`: the last one read
+    pub fn get(&self, name: &str) -> Option<&ConfigEntry> {
+        self.get_all(name).ok()?.pop()
+    }
+
+    // The winning value of `name` as a string; a bare key reads as empty
+    pub fn get_string(&self, name: &str) -> Option<String> {
+        self.get(name)
+            .map(|entry| entry.value.clone().unwrap_or_default())
+    }
+
+    // The winning value of `name` as a boolean; a bare key means true, and
+    // anything git would not accept as a boolean reads as unset
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        match self.get(name)?.value.as_deref() {
+            None => Some(true),
+            Some(value) => match value.to_ascii_lowercase().as_str() {
+                "true" | "yes" | "on" | "1" => Some(true),
+                "false" | "no" | "off" | "0" | "" => Some(false),
+                _ => None,
+            },
+        }
+    }
+}
+
+// Helper function to evaluate an includeIf condition such as "gitdir:~/work/"
+fn include_condition(condition: &str, file_dir: &Path, git_dir: Option<&Path>) -> bool {
+    let (pattern, case_insensitive) = match condition.split_once(':') {
+        Some(("gitdir", pattern)) => (pattern, false),
+        Some(("gitdir/i", pattern)) => (pattern, true),
+        _ => return false,
+    };
+    let Some(git_dir) = git_dir.and_then(|dir| dir.canonicalize().ok()) else {
+        return false;
+    };
+
+    let mut pattern = match pattern.strip_prefix("./") {
+        Some(relative) => file_dir.join(relative).display().to_string(),
+        None => expand_home(pattern).display().to_string(),
+    };
+    if !pattern.starts_with('/') {
+        pattern.insert_str(0, "**/");
+    }
+    if pattern.ends_with('/') {
+        pattern.push_str("**");
+    }
+
+    let mut git_dir = git_dir.display().to_string();
+    if case_insensitive {
+        pattern = pattern.to_lowercase();
+        git_dir = git_dir.to_lowercase();
+    }
+    wildmatch(pattern.as_bytes(), git_dir.as_bytes())
+}
+
+impl Repository {
+    // All settings that apply to this repository
+    pub fn config(&self) -> Result<Config> {
+        Config::load(Some(self.git_dir()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::ScratchDir;
+
+    fn parse(content: &str) -> Result<ConfigFile> {
+        ConfigFile::parse("config", content.to_string(), ConfigScope::Local, "config")
+    }
+
+    // Helper function to list a file's entries as --list prints them
+    fn listing(file: &ConfigFile) -> Vec<String> {
+        file.entries()
+            .iter()
+            .map(|entry| match &entry.value {
+                Some(value) => format!("{}={}", entry.name(), value),
+                None => entry.name(),
+            })
+            .collect()
+    }
+
+    // Helper function to apply an edit and return the resulting text
+    fn edited(content: &str, edit: impl FnOnce(&mut ConfigFile) -> Result<()>) -> String {
+        let mut file = parse(content).unwrap();
+        edit(&mut file).unwrap();
+        file.content
+    }
+
+    // Helper function to name a file of a scratch directory as a global config
+    fn file(dir: &ScratchDir, name: &str) -> (PathBuf, ConfigScope, String) {
+        let path = dir.join(name);
+        let origin = path.display().to_string();
+        (path, ConfigScope::Global, origin)
+    }
+
+    #[test]
+    fn parses_sections_and_keys() {
+        let file = parse(
+            "\u{feff}# comment\n\
+             [Core]\n\
+             \tBare\n\
+             \tfileMode = false ; trailing comment\n\
+             [remote \"Origin\"]\n\
+             url = one\n\
+             url = two\n\
+             [Legacy.Sub] key = inline\n",
+        )
+        .unwrap();
+        assert_eq!(
+            listing(&file),
+            [
+                "core.bare",
+                "core.filemode=false",
+                "remote.Origin.url=one",
+                "remote.Origin.url=two",
+                "legacy.sub.key=inline",
+            ]
+        );
+    }
+
+    #[test]
+    fn decodes_values() {
+        let file = parse(
+            "[a]\n\
+             \tquoted = \"  two # not\"   words   here  \n\
+             \tescapes = tab\\there\\nnew\\\\slash\\\"quote\n\
+             \tcontinued = first \\\n  second\n\
+             \tempty =\n\
+             \thash = a#b\n",
+        )
+        .unwrap();
+        assert_eq!(
+            listing(&file),
+            [
+                "a.quoted=  two # not   words   here",
+                "a.escapes=tab\there\nnew\\slash\"quote",
+                "a.continued=first   second",
+                "a.empty=",
+                "a.hash=a",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_the_bad_line() {
+        for (content, line) in [
+            ("[a]\nkey = \"open\n", 2),
+            ("[a]\n\n[b\n", 3),
+            ("[a \"sub\" ]\n", 1),
+            ("[a.b \"sub\"]\n", 1),
+            ("[a]\nk = bad\\q\n", 2),
+            ("[a]\n1key = x\n", 2),
+        ] {
+            match parse(content) {
+                Err(Error::BadConfigLine { line: found, .. }) => {
+                    assert_eq!(found, line, "{:?}", content)
+                }
+                other => panic!("{:?} parsed as {:?}", content, other.map(|f| listing(&f))),
+            }
+        }
+    }
+
+    #[test]
+    fn checks_names() {
+        assert_eq!(
+            parse_name("Remote.Origin.URL").unwrap(),
+            ("remote.Origin".to_string(), "url".to_string())
+        );
+        assert_eq!(
+            parse_name("a.b.c.d").unwrap(),
+            ("a.b.c".to_string(), "d".to_string())
+        );
+        for name in ["nodot", "a.", "a.1b", "a_b.c", ".key", "a.b_c"] {
+            assert!(
+                matches!(parse_name(name), Err(Error::InvalidConfigKey(_))),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn formatted_values_read_back_unchanged() {
+        for value in [
+            "plain",
+            " edge ",
+            "semi;colon",
+            "hash#",
+            "tab\tnew\nline",
+            "q\"b\\",
+        ] {
+            let content = format!("[a]\n\tk = {}\n", format_value(value));
+            let file = parse(&content).unwrap();
+            assert_eq!(
+                file.entries()[0].value.as_deref(),
+                Some(value),
+                "{}",
+                content
+            );
+        }
+        assert_eq!(format_header("a.Sub\"q.k"), "[a \"Sub\\\"q\"]\n");
+        assert_eq!(format_header("Core.k"), "[Core]\n");
+    }
+
+    #[test]
+    fn set_replaces_or_appends() {
+        let content = "[core]\n\tbare = false # keep me?\n[user]\n\tname = A\n";
+        assert_eq!(
+            edited(content, |file| file.set("core.bare", "true", false)),
+            "[core]\n\tbare = true\n[user]\n\tname = A\n"
+        );
+        assert_eq!(
+            edited(content, |file| file.set("core.editor", "vi", false)),
+            "[core]\n\tbare = false # keep me?\n\teditor = vi\n[user]\n\tname = A\n"
+        );
+        assert_eq!(
+            edited(content, |file| file.set("Remote.Origin.url", "x y", false)),
+            format!("{}[Remote \"Origin\"]\n\turl = x y\n", content)
+        );
+        assert_eq!(
+            edited("[user]\n\tname = A", |file| file.set(
+                "user.email",
+                "a@b",
+                false
+            )),
+            "[user]\n\tname = A\n\temail = a@b\n"
+        );
+    }
+
+    #[test]
+    fn set_refuses_to_pick_among_several_values() {
+        let content = "[a]\n\tk = 1\n\tk = 2\n";
+        let mut file = parse(content).unwrap();
+        assert!(matches!(
+            file.set("a.k", "3", false),
+            Err(Error::ConfigMultipleValues(_))
+        ));
+        file.set("a.k", "3", true).unwrap();
+        assert_eq!(file.content, "[a]\n\tk = 1\n\tk = 2\n\tk = 3\n");
+    }
+
+    #[test]
+    fn unset_removes_lines_and_emptied_sections() {
+        let content = "[a]\n\tk = 1\n\tother = x\n[b]\n\tk = 1\n\tk = 2\n[c]\n\tz = 0\n";
+        assert_eq!(
+            edited(content, |file| file.unset("a.k", false).map(drop)),
+            "[a]\n\tother = x\n[b]\n\tk = 1\n\tk = 2\n[c]\n\tz = 0\n"
+        );
+        assert_eq!(
+            edited(content, |file| file.unset("b.k", true).map(drop)),
+            "[a]\n\tk = 1\n\tother = x\n[c]\n\tz = 0\n"
+        );
+
+        let mut file = parse(content).unwrap();
+        assert!(matches!(
+            file.unset("b.k", false),
+            Err(Error::ConfigMultipleValues(_))
+        ));
+        assert_eq!(file.unset("b.missing", false).unwrap(), 0);
+    }
+
+    #[test]
+    fn writes_through_a_lock_file() {
+        let dir = ScratchDir::new("config-write", &[("config", "[a]\n\tk = 1\n")]);
+        let path = dir.join("config");
+        let mut file = ConfigFile::read(&path, ConfigScope::File, "config").unwrap();
+        file.set("a.k", "2", false).unwrap();
+        file.write().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[a]\n\tk = 2\n");
+        assert!(!dir.join("config.lock").exists());
+
+        let missing = ConfigFile::read(&dir.join("none"), ConfigScope::File, "none").unwrap();
+        assert!(missing.entries().is_empty());
+    }
+
+    #[test]
+    fn follows_includes_in_place() {
+        let dir = ScratchDir::new(
+            "config-include",
+            &[
+                (
+                    "main",
+                    "[a]\n\tk = before\n[include]\n\tpath = sub/inc\n[a]\n\tk = after\n",
+                ),
+                (
+                    "sub/inc",
+                    "[a]\n\tk = included\n[include]\n\tpath = nested\n",
+                ),
+                ("sub/nested", "[b]\n\tn = deep\n"),
+            ],
+        );
+        let config = Config::load_files(&[file(&dir, "main")], None, true).unwrap();
+        let values: Vec<_> = config
+            .get_all("a.k")
+            .unwrap()
+            .iter()
+            .map(|entry| entry.value.clone().unwrap())
+            .collect();
+        assert_eq!(values, ["before", "included", "after"]);
+        assert_eq!(config.get_string("b.n").as_deref(), Some("deep"));
+        let nested = config.get("b.n").unwrap();
+        assert!(nested.origin.ends_with("sub/nested"), "{}", nested.origin);
+
+        // Reading one file on its own leaves includes alone
+        let config = Config::load_files(&[file(&dir, "main")], None, false).unwrap();
+        assert_eq!(config.get("b.n"), None);
+    }
+
+    #[test]
+    fn stops_include_cycles() {
+        let dir = ScratchDir::new("config-cycle", &[("loop", "[include]\n\tpath = loop\n")]);
+        assert!(matches!(
+            Config::load_files(&[file(&dir, "loop")], None, true),
+            Err(Error::ConfigIncludeDepth { .. })
+        ));
+    }
+
+    #[test]
+    fn matches_include_if_gitdir() {
+        let dir = ScratchDir::new(
+            "config-includeif",
+            &[
+                (
+                    "main",
+                    "[includeIf \"gitdir:**/work/\"]\n\tpath = work.inc\n\
+                     [includeIf \"gitdir/i:**/PROJECT/.git\"]\n\tpath = project.inc\n\
+                     [includeIf \"gitdir:./other/\"]\n\tpath = other.inc\n",
+                ),
+                ("work.inc", "[w]\n\tv = yes\n"),
+                ("project.inc", "[p]\n\tv = yes\n"),
+                ("other.inc", "[o]\n\tv = yes\n"),
+                ("work/project/.git/HEAD", ""),
+                ("other/repo/.git/HEAD", ""),
+            ],
+        );
+
+        let git_dir = dir.join("work/project/.git");
+        let config = Config::load_files(&[file(&dir, "main")], Some(&git_dir), true).unwrap();
+        assert_eq!(config.get_string("w.v").as_deref(), Some("yes"));
+        assert_eq!(config.get_string("p.v").as_deref(), Some("yes"));
+        assert_eq!(config.get("o.v"), None);
+
+        let git_dir = dir.join("other/repo/.git");
+        let config = Config::load_files(&[file(&dir, "main")], Some(&git_dir), true).unwrap();
+        assert_eq!(config.get("w.v"), None);
+        assert_eq!(config.get_string("o.v").as_deref(), Some("yes"));
+
+        // Without a repository no gitdir condition holds
+        let config = Config::load_files(&[file(&dir, "main")], None, true).unwrap();
+        assert!(config
+            .entries()
+            .iter()
+            .all(|entry| entry.section.starts_with("includeif")));
+    }
+
+    #[test]
+    fn reads_booleans_like_git() {
+        let file =
+            parse("[b]\n\tbare\n\tyes = YES\n\toff = off\n\tone = 1\n\tempty =\n\tjunk = maybe\n")
+                .unwrap();
+        let config = Config {
+            entries: file.entries,
+        };
+        assert_eq!(config.get_bool("b.bare"), Some(true));
+        assert_eq!(config.get_bool("b.yes"), Some(true));
+        assert_eq!(config.get_bool("b.off"), Some(false));
+        assert_eq!(config.get_bool("b.one"), Some(true));
+        assert_eq!(config.get_bool("b.empty"), Some(false));
+        assert_eq!(config.get_bool("b.junk"), None);
+        assert_eq!(config.get_bool("b.missing"), None);
+        assert_eq!(config.get_string("b.bare").as_deref(), Some(""));
+    }
+}
@@ -0,0 +1,147 @@
+use crate::object::ObjectId;
+
+// Everything that can go wrong in this crate
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("not a valid object name {0}")]
+    InvalidObjectName(String),
+
+    #[error("object {0} not found")]
+    ObjectNotFound(ObjectId),
+
+    #[error("invalid object type \"{0}\"")]
+    InvalidObjectType(String),
+
+    #[error("object {id} is a {actual}, not a {expected}")]
+    UnexpectedObjectType {
+        id: ObjectId,
+        expected: &'static str,
+        actual: String,
+    },
+
+    #[error("{0} is not a valid object")]
+    InvalidObject(ObjectId),
+
+    #[error("{id} is not a valid '{expected}' object")]
+    InvalidTypedObject {
+        id: ObjectId,
+        expected: &'static str,
+    },
+
+    #[error("invalid tree entry '{name}': {reason}")]
+    InvalidTreeEntry { name: String, reason: &'static str },
+
+    #[error("corrupt object: {0}")]
+    CorruptObject(String),
+
+    #[error("corrupt pack: {0}")]
+    CorruptPack(String),
+
+    #[error("index file corrupt: {0}")]
+    CorruptIndex(String),
+
+    #[error("unable to create '{0}': File exists")]
+    LockExists(String),
+
+    #[error("path '{0}' is unmerged")]
+    Unmerged(String),
+
+    #[error("invalid object {mode:06o} {id} for '{path}'")]
+    InvalidIndexObject {
+        mode: u32,
+        id: ObjectId,
+        path: String,
+    },
+
+    #[error("prefix {0} not found")]
+    PrefixNotFound(String),
+
+    #[error("pathspec '{0}' did not match any files")]
+    PathspecNoMatch(String),
+
+    #[error("'{0}' is outside repository")]
+    OutsideRepository(String),
+
+    #[error("'{0}' does not have a commit checked out")]
+    GitlinkWithoutCommit(String),
+
+    #[error("not removing '{0}' recursively without -r")]
+    RecursiveRemovalRequired(String),
+
+    // Files rm refused to remove, with git's explanation of why
+    #[error("{0}")]
+    RemovalRefused(String),
+
+    #[error("invalid date format: {0}")]
+    InvalidDate(String),
+
+    // No email was configured and none could be guessed for the author or committer
+    #[error("unable to auto-detect email address (got '{guess}')")]
+    IdentityUnknown { role: &'static str, guess: String },
+
+    #[error("empty ident name (for <{0}>) not allowed")]
+    EmptyIdentName(String),
+
+    // A malformed config name, with git's explanation
+    #[error("{0}")]
+    InvalidConfigKey(String),
+
+    #[error("bad config line {line} in file {origin}")]
+    BadConfigLine { line: usize, origin: String },
+
+    // include.path nested deeper than git allows, most likely a cycle
+    #[error(
+        "exceeded maximum include depth ({}) while including\n\t{path}\nfrom\n\t{from}\n\
+         This might be due to circular includes.",
+        crate::config::MAX_INCLUDE_DEPTH
+    )]
+    ConfigIncludeDepth { path: String, from: String },
+
+    #[error("cannot overwrite multiple values with a single value")]
+    ConfigMultipleValues(String),
+
+    #[error("unexpected line in packed-refs: {0}")]
+    CorruptPackedRefs(String),
+
+    #[error("cannot lock ref '{name}': {reason}")]
+    RefLockFailed { name: String, reason: String },
+
+    #[error("gpg failed to sign the data")]
+    SigningFailed,
+
+    #[error("Aborting commit due to empty commit message.")]
+    EmptyCommitMessage,
+
+    // The index records the same tree as the commit it would follow
+    #[error("nothing to commit")]
+    NothingToCommit,
+
+    #[error("You have nothing to amend.")]
+    NothingToAmend,
+
+    #[error("invalid ref: {0}")]
+    InvalidRef(String),
+
+    #[error("not a git repository: {0}")]
+    NotARepository(String),
+
+    #[error("not a git repository (or any of the parent directories): .git")]
+    RepositoryNotFound,
+
+    #[error("this operation must be run in a work tree")]
+    BareRepository,
+
+    #[error("protocol error: {0}")]
+    Protocol(String),
+
+    #[error("transport error: {0}")]
+    Transport(String),
+
+    #[error("unsupported operation: {0}")]
+    Unsupported(String),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
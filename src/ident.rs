@@ -0,0 +1,533 @@
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::repository::Repository;
+use std::env;
+use std::ffi::CStr;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Which side of a commit an identity is for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Author,
+    Committer,
+}
+
+impl Role {
+    // Prefix of the GIT_<ROLE>_NAME, _EMAIL and _DATE variables
+    fn env_prefix(self) -> &'static str {
+        match self {
+            Role::Author => "GIT_AUTHOR",
+            Role::Committer => "GIT_COMMITTER",
+        }
+    }
+
+    // Config section that overrides user.name and user.email for this role
+    fn config_section(self) -> &'static str {
+        match self {
+            Role::Author => "author",
+            Role::Committer => "committer",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Role::Author => "Author",
+            Role::Committer => "Committer",
+        }
+    }
+}
+
+// Who did something and when, as recorded in commit and tag headers:
+// "Name <email> 1719158400 +0200"
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    pub time: i64,
+    // Offset from UTC in minutes
+    pub offset: i32,
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.offset < 0 { '-' } else { '+' };
+        let offset = self.offset.abs();
+        write!(
+            f,
+            "{} <{}> {} {}{:02}{:02}",
+            self.name,
+            self.email,
+            self.time,
+            sign,
+            offset / 60,
+            offset % 60
+        )
+    }
+}
+
+impl Signature {
+    // Parse a "Name <email> 1719158400 +0200" header value
+    pub fn parse(value: &str) -> Option<Self> {
+        let (name, rest) = value.split_once('<')?;
+        let (email, rest) = rest.split_once('>')?;
+        let mut fields = rest.split_whitespace();
+        let time = fields.next()?.parse().ok()?;
+        let offset = parse_offset(fields.next()?)?;
+        Some(Self {
+            name: name.trim_end().to_string(),
+            email: email.to_string(),
+            time,
+            offset,
+        })
+    }
+
+    // The date in git's default format, in the signature's own time zone:
+    // "Tue Nov 14 23:13:20 2023 +0100"
+    pub fn date(&self) -> String {
+        let local = self.time + self.offset as i64 * 60;
+        let days = local.div_euclid(86400);
+        let seconds = local.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+        let sign = if self.offset < 0 { '-' } else { '+' };
+        let offset = self.offset.abs();
+        format!(
+            "{} {} {} {:02}:{:02}:{:02} {} {}{:02}{:02}",
+            WEEKDAYS[(days + 4).rem_euclid(7) as usize],
+            MONTHS[month as usize - 1],
+            day,
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+            year,
+            sign,
+            offset / 60,
+            offset % 60
+        )
+    }
+}
+
+// Helper function to strip what git considers crud from the ends of a name or
+// email, and the characters that would break the header format
+fn sanitize(value: &str) -> String {
+    let is_crud = |c: char| {
+        c.is_ascii_whitespace()
+            || matches!(c, '.' | ',' | ':' | ';' | '<' | '>' | '"' | '\\' | '\'')
+    };
+    value
+        .trim_matches(is_crud)
+        .chars()
+        .filter(|c| !matches!(c, '<' | '>' | '\n'))
+        .collect()
+}
+
+// Helper function to find the local time zone's offset from UTC, in minutes,
+// at a given moment
+fn local_offset(time: i64) -> i32 {
+    // SAFETY: localtime_r only writes to the tm we hand it
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let time = time as libc::time_t;
+    match unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        true => 0,
+        false => (tm.tm_gmtoff / 60) as i32,
+    }
+}
+
+// The current time with the local time zone offset
+pub fn now() -> (i64, i32) {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64);
+    (time, local_offset(time))
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+// Helper function to count days from 1970-01-01 to a civil date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// Helper function to turn days since 1970-01-01 back into a civil date
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+// Helper function to parse a time zone offset: +hhmm, -hh:mm, +hh, Z, UTC or GMT
+fn parse_offset(zone: &str) -> Option<i32> {
+    if matches!(zone, "Z" | "UTC" | "GMT") {
+        return Some(0);
+    }
+    let sign = match zone.as_bytes().first()? {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    let digits = zone[1..].replace(':', "");
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes) = match digits.len() {
+        2 => (digits.parse::<i32>().ok()?, 0),
+        4 => (digits[..2].parse().ok()?, digits[2..].parse::<i32>().ok()?),
+        _ => return None,
+    };
+    (minutes < 60).then_some(sign * (hours * 60 + minutes))
+}
+
+// Helper function to parse "HH:MM[:SS[.fraction]]" into seconds since midnight
+fn parse_time_of_day(time: &str) -> Option<i64> {
+    let time = time.split_once('.').map_or(time, |(whole, _)| whole);
+    let mut fields = time.split(':').map(|field| field.parse::<i64>().ok());
+    let hours = fields.next()??;
+    let minutes = fields.next()??;
+    let seconds = fields.next().unwrap_or(Some(0))?;
+    if fields.next().is_some() || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    Some(hours * 3600 + minutes * 60 + seconds)
+}
+
+// Helper function to turn a wall clock time into a timestamp, using the local
+// time zone when no offset was given
+fn resolve_local(seconds: i64, offset: Option<i32>) -> (i64, i32) {
+    let offset = offset.unwrap_or_else(|| {
+        // The offset depends on the moment itself, so settle it from a first guess
+        let guess = local_offset(seconds);
+        local_offset(seconds - guess as i64 * 60)
+    });
+    (seconds - offset as i64 * 60, offset)
+}
+
+// Helper function to parse "[Day, ]DD Mon YYYY HH:MM[:SS] [zone]", as in RFC 2822
+fn parse_rfc2822(date: &str) -> Option<(i64, i32)> {
+    let date = date.split_once(',').map_or(date, |(_, rest)| rest);
+    let fields: Vec<&str> = date.split_whitespace().collect();
+    let (day, month, year, time, zone) = match fields.as_slice() {
+        [day, month, year, time] => (day, month, year, time, None),
+        [day, month, year, time, zone] => (day, month, year, time, Some(*zone)),
+        _ => return None,
+    };
+
+    let month = MONTHS
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(month.get(..3).unwrap_or_default()))?
+        as i64
+        + 1;
+    let day: i64 = day.parse().ok()?;
+    let year: i64 = year.parse().ok()?;
+    if !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let offset = match zone {
+        Some(zone) => Some(parse_offset(zone)?),
+        None => None,
+    };
+    let seconds = days_from_civil(year, month, day) * 86400 + parse_time_of_day(time)?;
+    Some(resolve_local(seconds, offset))
+}
+
+// Helper function to parse "YYYY-MM-DD[T ]HH:MM[:SS[.fraction]][zone]", as in ISO 8601
+fn parse_iso8601(date: &str) -> Option<(i64, i32)> {
+    let (day, rest) = date.split_at(date.find(['T', ' '])?);
+    let mut parts = day.split('-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let rest = rest[1..].trim();
+    let zone_start = rest.find(['Z', '+', '-', ' ']).unwrap_or(rest.len());
+    let (time, zone) = rest.split_at(zone_start);
+    let zone = zone.trim();
+    let offset = match zone.is_empty() {
+        true => None,
+        false => Some(parse_offset(zone)?),
+    };
+
+    let seconds = days_from_civil(year, month, day) * 86400 + parse_time_of_day(time)?;
+    Some(resolve_local(seconds, offset))
+}
+
+// Helper function to parse "@<seconds> [zone]" or git's raw "<seconds> <zone>"
+fn parse_timestamp(date: &str) -> Option<(i64, i32)> {
+    let (raw, date) = match date.strip_prefix('@') {
+        Some(date) => (false, date),
+        None => (true, date),
+    };
+    let mut fields = date.split_whitespace();
+    let time: i64 = fields.next()?.parse().ok()?;
+    let offset = match fields.next() {
+        Some(zone) => parse_offset(zone)?,
+        None if raw => return None,
+        None => local_offset(time),
+    };
+    fields.next().is_none().then_some((time, offset))
+}
+
+// Parse a date the way GIT_AUTHOR_DATE and GIT_COMMITTER_DATE accept it:
+// RFC 2822, ISO 8601, "@<unix seconds>" or the raw "<seconds> <zone>" form
+pub fn parse_date(date: &str) -> Result<(i64, i32)> {
+    let trimmed = date.trim();
+    parse_timestamp(trimmed)
+        .or_else(|| parse_iso8601(trimmed))
+        .or_else(|| parse_rfc2822(trimmed))
+        .ok_or_else(|| Error::InvalidDate(date.to_string()))
+}
+
+// Helper function to read the user's full name and login from the password database
+fn passwd_name() -> Option<(String, String)> {
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut result = std::ptr::null_mut();
+    // SAFETY: getpwuid_r fills `passwd` with pointers into `buffer`, which outlives them
+    let status = unsafe {
+        libc::getpwuid_r(
+            libc::getuid(),
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if status != 0 || result.is_null() {
+        return None;
+    }
+
+    let field = |pointer: *const libc::c_char| match pointer.is_null() {
+        true => String::new(),
+        false => unsafe { CStr::from_ptr(pointer) }
+            .to_string_lossy()
+            .into_owned(),
+    };
+    // The GECOS field holds the full name before any comma
+    let gecos = field(passwd.pw_gecos);
+    let full_name = gecos.split(',').next().unwrap_or_default().to_string();
+    Some((full_name, field(passwd.pw_name)))
+}
+
+// Helper function to read the host name
+fn host_name() -> String {
+    let mut buffer = [0 as libc::c_char; 256];
+    // SAFETY: gethostname writes at most buffer.len() bytes
+    if unsafe { libc::gethostname(buffer.as_mut_ptr(), buffer.len()) } != 0 {
+        return String::new();
+    }
+    buffer[buffer.len() - 1] = 0;
+    unsafe { CStr::from_ptr(buffer.as_ptr()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+impl Repository {
+    // Resolve who is acting in `role`: GIT_<ROLE>_NAME/EMAIL/DATE, then the
+    // <role>.name/email and user.name/email settings, then what the system knows
+    pub fn signature(&self, role: Role) -> Result<Signature> {
+        identity(&self.config()?, role, true)
+    }
+}
+
+// The committer as recorded in reflogs, where git settles for whatever it can
+// guess rather than refusing
+pub(crate) fn reflog_signature(config: &Config) -> Result<Signature> {
+    identity(config, Role::Committer, false)
+}
+
+// Helper function to resolve an identity from already loaded settings; unless
+// `strict`, a missing email or name falls back to the guessed one instead of failing
+pub(crate) fn identity(config: &Config, role: Role, strict: bool) -> Result<Signature> {
+    let prefix = role.env_prefix();
+    let setting = |key: &str| {
+        config
+            .get_string(&format!("{}.{}", role.config_section(), key))
+            .or_else(|| config.get_string(&format!("user.{}", key)))
+    };
+    let passwd = passwd_name();
+    let login = passwd.as_ref().map_or("", |(_, login)| login.as_str());
+
+    let email = env::var(format!("{}_EMAIL", prefix))
+        .ok()
+        .or_else(|| setting("email"))
+        .or_else(|| env::var("EMAIL").ok());
+    let email = match email {
+        Some(email) => email,
+        None => {
+            // Without a domain in the host name there is no plausible address
+            let host = host_name();
+            match host.contains('.') {
+                true => format!("{}@{}", login, host),
+                false if !strict => format!("{}@{}.(none)", login, host),
+                false => {
+                    return Err(Error::IdentityUnknown {
+                        role: role.label(),
+                        guess: format!("{}@{}.(none)", login, host),
+                    })
+                }
+            }
+        }
+    };
+    let email = sanitize(&email);
+
+    let name = env::var(format!("{}_NAME", prefix))
+        .ok()
+        .or_else(|| setting("name"))
+        .or_else(|| {
+            passwd
+                .as_ref()
+                .map(|(full_name, login)| match full_name.is_empty() {
+                    true => login.clone(),
+                    false => full_name.clone(),
+                })
+        })
+        .unwrap_or_default();
+    let mut name = sanitize(&name);
+    if name.is_empty() {
+        match strict {
+            true => return Err(Error::EmptyIdentName(email)),
+            false => name = login.to_string(),
+        }
+    }
+
+    let (time, offset) = match env::var(format!("{}_DATE", prefix)) {
+        Ok(date) => parse_date(&date)?,
+        Err(_) => now(),
+    };
+    Ok(Signature {
+        name,
+        email,
+        time,
+        offset,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(value: &str) -> Option<(i64, i32)> {
+        parse_date(value).ok()
+    }
+
+    #[test]
+    fn parses_rfc2822_dates() {
+        assert_eq!(
+            date("Thu, 07 Apr 2005 22:13:13 +0200"),
+            Some((1112904793, 120))
+        );
+        // The weekday is optional and seconds may be left out
+        assert_eq!(date("7 Apr 2005 22:13 +0200"), Some((1112904780, 120)));
+        assert_eq!(date("07 april 2005 22:13:13 GMT"), Some((1112911993, 0)));
+        assert_eq!(date("32 Apr 2005 22:13:13 +0200"), None);
+        assert_eq!(date("07 Foo 2005 22:13:13 +0200"), None);
+    }
+
+    #[test]
+    fn parses_iso8601_dates() {
+        assert_eq!(date("2005-04-07T22:13:13Z"), Some((1112911993, 0)));
+        assert_eq!(date("2005-04-07 22:13:13 -05:30"), Some((1112931793, -330)));
+        assert_eq!(date("2005-04-07T22:13:13.25+0000"), Some((1112911993, 0)));
+        assert_eq!(date("2000-02-29T12:00:00+0000"), Some((951825600, 0)));
+        assert_eq!(date("1969-12-31T23:59:59Z"), Some((-1, 0)));
+        assert_eq!(date("2005-13-07T22:13:13Z"), None);
+        assert_eq!(date("2005-04-07T24:00:00Z"), None);
+    }
+
+    #[test]
+    fn parses_timestamps() {
+        assert_eq!(date("@1112911993 +0200"), Some((1112911993, 120)));
+        assert_eq!(date("1112911993 -0700"), Some((1112911993, -420)));
+        assert_eq!(date("  1112911993 +0000\n"), Some((1112911993, 0)));
+        // The raw form needs its zone
+        assert_eq!(date("1112911993"), None);
+        assert_eq!(date("@1112911993 +0200 extra"), None);
+        assert!(matches!(
+            parse_date("yesterday"),
+            Err(Error::InvalidDate(value)) if value == "yesterday"
+        ));
+    }
+
+    #[test]
+    fn parses_offsets() {
+        assert_eq!(parse_offset("+0200"), Some(120));
+        assert_eq!(parse_offset("-0930"), Some(-570));
+        assert_eq!(parse_offset("+05:45"), Some(345));
+        assert_eq!(parse_offset("-03"), Some(-180));
+        assert_eq!(parse_offset("Z"), Some(0));
+        assert_eq!(parse_offset("UTC"), Some(0));
+        assert_eq!(parse_offset("+0260"), None);
+        assert_eq!(parse_offset("0200"), None);
+        assert_eq!(parse_offset("+020"), None);
+        assert_eq!(parse_offset("+02a0"), None);
+    }
+
+    #[test]
+    fn converts_between_days_and_dates() {
+        for (days, civil) in [
+            (0, (1970, 1, 1)),
+            (-1, (1969, 12, 31)),
+            (11016, (2000, 2, 29)),
+            (12880, (2005, 4, 7)),
+            (-719468, (0, 3, 1)),
+        ] {
+            assert_eq!(civil_from_days(days), civil);
+            assert_eq!(days_from_civil(civil.0, civil.1, civil.2), days);
+        }
+    }
+
+    #[test]
+    fn parses_and_prints_idents() {
+        let value = "A U Thor <author@example.com> 1112911993 -0700";
+        let signature = Signature::parse(value).unwrap();
+        assert_eq!(
+            signature,
+            Signature {
+                name: "A U Thor".to_string(),
+                email: "author@example.com".to_string(),
+                time: 1112911993,
+                offset: -420,
+            }
+        );
+        assert_eq!(signature.to_string(), value);
+        assert_eq!(signature.date(), "Thu Apr 7 15:13:13 2005 -0700");
+
+        // An empty name and email are still an ident
+        let empty = Signature::parse("<> 0 +0000").unwrap();
+        assert_eq!((empty.name.as_str(), empty.email.as_str()), ("", ""));
+        assert_eq!(empty.date(), "Thu Jan 1 00:00:00 1970 +0000");
+
+        assert_eq!(Signature::parse("No Email 1112911993 +0000"), None);
+        assert_eq!(Signature::parse("A <a@b> 1112911993"), None);
+        assert_eq!(Signature::parse("A <a@b> soon +0000"), None);
+    }
+
+    #[test]
+    fn sanitizes_names_and_emails() {
+        assert_eq!(sanitize("  \"Jane Doe\". "), "Jane Doe");
+        assert_eq!(sanitize("<jane@example.com>"), "jane@example.com");
+        assert_eq!(sanitize("Ja<ne>\nDoe"), "JaneDoe");
+        assert_eq!(sanitize("...,;:"), "");
+    }
+}
@@ -0,0 +1,650 @@
+use crate::error::Result;
+use crate::repository::{path_from_bytes, xdg_config_home, Repository};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Outcome of matching part of a glob against part of a path. The two abort
+// variants let a failed `*` or `**` stop outer wildcards from retrying in vain.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Wild {
+    Match,
+    NoMatch,
+    AbortAll,
+    AbortToDoubleStar,
+}
+
+// Helper function to test a byte against a POSIX class name like "alpha"
+fn matches_class(class: &[u8], b: u8) -> Option<bool> {
+    Some(match class {
+        b"alnum" => b.is_ascii_alphanumeric(),
+        b"alpha" => b.is_ascii_alphabetic(),
+        b"blank" => b == b' ' || b == b'\t',
+        b"cntrl" => b.is_ascii_control(),
+        b"digit" => b.is_ascii_digit(),
+        b"graph" => b.is_ascii_graphic(),
+        b"lower" => b.is_ascii_lowercase(),
+        b"print" => b.is_ascii_graphic() || b == b' ',
+        b"punct" => b.is_ascii_punctuation(),
+        b"space" => b.is_ascii_whitespace() || b == 0x0b,
+        b"upper" => b.is_ascii_uppercase(),
+        b"xdigit" => b.is_ascii_hexdigit(),
+        _ => return None,
+    })
+}
+
+// Helper function to match a bracket expression starting just after '[';
+// returns whether `b` matched and the pattern index just past the closing ']'
+fn match_bracket(pattern: &[u8], mut p: usize, b: u8) -> Option<(bool, usize)> {
+    let negated = matches!(pattern.get(p), Some(b'!' | b'^'));
+    if negated {
+        p += 1;
+    }
+
+    let mut matched = false;
+    let mut previous = None;
+    let mut first = true;
+    loop {
+        let mut c = *pattern.get(p)?;
+        if c == b']' && !first {
+            break;
+        }
+        first = false;
+
+        if c == b'\\' {
+            p += 1;
+            c = *pattern.get(p)?;
+            matched |= c == b;
+        } else if c == b'-'
+            && previous.is_some()
+            && !matches!(pattern.get(p + 1), None | Some(b']'))
+        {
+            p += 1;
+            let mut end = pattern[p];
+            if end == b'\\' {
+                p += 1;
+                end = *pattern.get(p)?;
+            }
+            if let Some(start) = previous {
+                matched |= (start..=end).contains(&b);
+            }
+            // A range end cannot start another range
+            previous = None;
+            p += 1;
+            continue;
+        } else if c == b'[' && pattern.get(p + 1) == Some(&b':') {
+            let name_start = p + 2;
+            let name_len = pattern[name_start..]
+                .windows(2)
+                .position(|pair| pair == b":]")?;
+            let class = &pattern[name_start..name_start + name_len];
+            // An unknown class name makes the whole pattern invalid
+            matched |= matches_class(class, b)?;
+            p = name_start + name_len + 2;
+            previous = None;
+            continue;
+        } else {
+            matched |= c == b;
+        }
+        previous = Some(c);
+        p += 1;
+    }
+
+    Some((matched != negated, p + 1))
+}
+
+// Recursive function matching `pattern` against `text` the way git's
+// wildmatch does with WM_PATHNAME: wildcards never cross '/' except `**`
+fn wild(pattern: &[u8], text: &[u8]) -> Wild {
+    let mut p = 0;
+    let mut t = 0;
+    while p < pattern.len() {
+        let c = pattern[p];
+        if t >= text.len() && c != b'*' {
+            return Wild::AbortAll;
+        }
+
+        match c {
+            b'\\' => {
+                p += 1;
+                if pattern.get(p) != Some(&text[t]) {
+                    return Wild::NoMatch;
+                }
+            }
+            b'?' => {
+                if text[t] == b'/' {
+                    return Wild::NoMatch;
+                }
+            }
+            b'*' => {
+                let star_start = p;
+                p += 1;
+                let mut match_slash = false;
+                if pattern.get(p) == Some(&b'*') {
+                    while pattern.get(p) == Some(&b'*') {
+                        p += 1;
+                    }
+                    // Only a `**` standing alone between slashes spans directories
+                    let after_slash = star_start == 0 || pattern[star_start - 1] == b'/';
+                    if after_slash && matches!(pattern.get(p), None | Some(b'/')) {
+                        if pattern.get(p) == Some(&b'/')
+                            && wild(&pattern[p + 1..], &text[t..]) == Wild::Match
+                        {
+                            return Wild::Match;
+                        }
+                        match_slash = true;
+                    }
+                }
+
+                if p == pattern.len() {
+                    if !match_slash && text[t..].contains(&b'/') {
+                        return Wild::NoMatch;
+                    }
+                    return Wild::Match;
+                }
+                if !match_slash && pattern[p] == b'/' {
+                    match text[t..].iter().position(|&b| b == b'/') {
+                        Some(slash) => {
+                            t += slash;
+                            continue;
+                        }
+                        None => return Wild::NoMatch,
+                    }
+                }
+
+                while t < text.len() {
+                    match wild(&pattern[p..], &text[t..]) {
+                        Wild::NoMatch => {
+                            if !match_slash && text[t] == b'/' {
+                                return Wild::AbortToDoubleStar;
+                            }
+                        }
+                        Wild::AbortToDoubleStar if match_slash => {}
+                        result => return result,
+                    }
+                    t += 1;
+                }
+                return Wild::AbortAll;
+            }
+            b'[' => {
+                let Some((matched, next)) = match_bracket(pattern, p + 1, text[t]) else {
+                    return Wild::AbortAll;
+                };
+                if !matched || text[t] == b'/' {
+                    return Wild::NoMatch;
+                }
+                p = next;
+                t += 1;
+                continue;
+            }
+            _ => {
+                if c != text[t] {
+                    return Wild::NoMatch;
+                }
+            }
+        }
+        p += 1;
+        t += 1;
+    }
+
+    match t == text.len() {
+        true => Wild::Match,
+        false => Wild::NoMatch,
+    }
+}
+
+// Helper function to match a gitignore glob against a path
+pub fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+    wild(pattern, text) == Wild::Match
+}
+
+// Helper function to strip unescaped trailing spaces from a pattern line
+fn trim_trailing_spaces(line: &[u8]) -> &[u8] {
+    let mut end = line.len();
+    while end > 0 && line[end - 1] == b' ' {
+        let backslashes = line[..end - 1]
+            .iter()
+            .rev()
+            .take_while(|&&b| b == b'\\')
+            .count();
+        if backslashes % 2 == 1 {
+            break;
+        }
+        end -= 1;
+    }
+    &line[..end]
+}
+
+// One line of an ignore file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IgnorePattern {
+    // The line as written, for `check-ignore -v`
+    pub text: Vec<u8>,
+    // Where the line came from and its 1-based line number
+    pub source: String,
+    pub line_number: usize,
+    // A `!` pattern re-includes what an earlier pattern excluded
+    pub negated: bool,
+    glob: Vec<u8>,
+    // The directory of the .gitignore declaring it, with a trailing '/'
+    base: Vec<u8>,
+    dir_only: bool,
+    // Patterns without a slash match a name at any depth below `base`
+    match_name: bool,
+}
+
+impl IgnorePattern {
+    // Parse one line of an ignore file; None for blank lines and comments
+    pub fn parse(line: &[u8], source: &str, line_number: usize, base: &[u8]) -> Option<Self> {
+        let text = trim_trailing_spaces(line);
+        if text.is_empty() || text[0] == b'#' {
+            return None;
+        }
+
+        let mut glob = text;
+        let negated = glob[0] == b'!';
+        if negated {
+            glob = &glob[1..];
+        }
+        let dir_only = glob.last() == Some(&b'/');
+        if dir_only {
+            glob = &glob[..glob.len() - 1];
+        }
+        let match_name = !glob.contains(&b'/');
+        if let Some(anchored) = glob.strip_prefix(b"/") {
+            glob = anchored;
+        }
+        if glob.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            text: text.to_vec(),
+            source: source.to_string(),
+            line_number,
+            negated,
+            glob: glob.to_vec(),
+            base: base.to_vec(),
+            dir_only,
+            match_name,
+        })
+    }
+
+    // Whether this pattern applies to `path`, relative to the work tree root
+    pub fn matches(&self, path: &[u8], is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let Some(relative) = path.strip_prefix(self.base.as_slice()) else {
+            return false;
+        };
+        match self.match_name {
+            true => {
+                let name_start = relative
+                    .iter()
+                    .rposition(|&b| b == b'/')
+                    .map_or(0, |i| i + 1);
+                wildmatch(&self.glob, &relative[name_start..])
+            }
+            false => wildmatch(&self.glob, relative),
+        }
+    }
+}
+
+// The patterns of one ignore file, in file order
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IgnoreList {
+    pub patterns: Vec<IgnorePattern>,
+}
+
+impl IgnoreList {
+    // Parse an ignore file whose patterns are relative to the directory `base`
+    pub fn parse(content: &[u8], source: &str, base: &[u8]) -> Self {
+        let patterns = content
+            .split(|&b| b == b'\n')
+            .enumerate()
+            .filter_map(|(i, line)| IgnorePattern::parse(line, source, i + 1, base))
+            .collect();
+        Self { patterns }
+    }
+
+    // Read an ignore file, treating a missing one as empty
+    pub fn read(path: &Path, source: &str, base: &[u8]) -> Result<Self> {
+        match fs::read(path) {
+            Ok(content) => Ok(Self::parse(&content, source, base)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // The last pattern matching `path`, which decides whether it is excluded
+    pub fn last_match(&self, path: &[u8], is_dir: bool) -> Option<&IgnorePattern> {
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| pattern.matches(path, is_dir))
+    }
+}
+
+// The pattern that decided a path's fate, and the path it matched: the path
+// itself or, when a whole directory is excluded, that directory
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IgnoreMatch {
+    pub path: Vec<u8>,
+    pub pattern: IgnorePattern,
+}
+
+// Every ignore rule of a work tree. A .gitignore beats those of its parent
+// directories, which beat .git/info/exclude, which beats core.excludesFile.
+#[derive(Clone, Debug)]
+pub struct IgnoreRules {
+    work_tree: PathBuf,
+    // .gitignore files by directory ("" for the root, else "dir/"), read lazily
+    directories: HashMap<Vec<u8>, IgnoreList>,
+    // .git/info/exclude followed by core.excludesFile
+    global: Vec<IgnoreList>,
+}
+
+impl IgnoreRules {
+    pub fn new(work_tree: impl Into<PathBuf>, global: Vec<IgnoreList>) -> Self {
+        Self {
+            work_tree: work_tree.into(),
+            directories: HashMap::new(),
+            global,
+        }
+    }
+
+    // Read the .gitignore of `dir` ("" or "dir/") unless already loaded
+    fn load_directory(&mut self, dir: &[u8]) -> Result<()> {
+        if !self.directories.contains_key(dir) {
+            let mut source = dir.to_vec();
+            source.extend_from_slice(b".gitignore");
+            let path = self.work_tree.join(path_from_bytes(&source));
+            let list = IgnoreList::read(&path, &String::from_utf8_lossy(&source), dir)?;
+            self.directories.insert(dir.to_vec(), list);
+        }
+        Ok(())
+    }
+
+    // The pattern deciding `path` itself, assuming its directories are not
+    // excluded; the directory walker checks each level on the way down
+    pub fn match_entry(&mut self, path: &[u8], is_dir: bool) -> Result<Option<&IgnorePattern>> {
+        let dirs: Vec<usize> = std::iter::once(0)
+            .chain(
+                path.iter()
+                    .enumerate()
+                    .filter(|(_, &b)| b == b'/')
+                    .map(|(i, _)| i + 1),
+            )
+            .collect();
+        for &end in &dirs {
+            self.load_directory(&path[..end])?;
+        }
+
+        let deepest_first = dirs
+            .iter()
+            .rev()
+            .map(|&end| &self.directories[&path[..end]]);
+        Ok(deepest_first
+            .chain(self.global.iter())
+            .find_map(|list| list.last_match(path, is_dir)))
+    }
+
+    // The pattern deciding whether `path` is ignored, looking at its parent
+    // directories first since nothing inside an excluded directory can be re-included
+    pub fn matching(&mut self, path: &[u8], is_dir: bool) -> Result<Option<IgnoreMatch>> {
+        let parents: Vec<usize> = path
+            .iter()
+            .enumerate()
+            .filter(|(_, &b)| b == b'/')
+            .map(|(i, _)| i)
+            .collect();
+        for end in parents {
+            if let Some(pattern) = self.match_entry(&path[..end], true)? {
+                if !pattern.negated {
+                    return Ok(Some(IgnoreMatch {
+                        path: path[..end].to_vec(),
+                        pattern: pattern.clone(),
+                    }));
+                }
+            }
+        }
+
+        Ok(self.match_entry(path, is_dir)?.map(|pattern| IgnoreMatch {
+            path: path.to_vec(),
+            pattern: pattern.clone(),
+        }))
+    }
+
+    // Whether `path` is excluded by some rule
+    pub fn is_ignored(&mut self, path: &[u8], is_dir: bool) -> Result<bool> {
+        Ok(self
+            .matching(path, is_dir)?
+            .is_some_and(|found| !found.pattern.negated))
+    }
+}
+
+// Helper function to expand a leading "~/" the way git does for config paths
+pub(crate) fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+impl Repository {
+    // The global ignore file: core.excludesFile from the repository or user
+    // config, defaulting to $XDG_CONFIG_HOME/git/ignore
+    fn excludes_file(&self) -> Result<Option<PathBuf>> {
+        Ok(self
+            .config()?
+            .get_string("core.excludesfile")
+            .map(|setting| expand_home(&setting))
+            .or_else(|| xdg_config_home().map(|xdg| xdg.join("git/ignore"))))
+    }
+
+    // Load the ignore rules that apply to the work tree
+    pub fn ignore_rules(&self) -> Result<IgnoreRules> {
+        let work_tree = self.require_work_tree()?;
+
+        // Name the exclude file the way git does: relative to the work tree when inside it
+        let info_exclude = self.git_dir().join("info/exclude");
+        let info_source = info_exclude
+            .strip_prefix(work_tree)
+            .unwrap_or(&info_exclude)
+            .display()
+            .to_string();
+
+        let mut global = vec![IgnoreList::read(&info_exclude, &info_source, b"")?];
+        if let Some(excludes_file) = self.excludes_file()? {
+            let source = excludes_file.display().to_string();
+            global.push(IgnoreList::read(&excludes_file, &source, b"")?);
+        }
+        Ok(IgnoreRules::new(work_tree, global))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::ScratchDir;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        wildmatch(pattern.as_bytes(), text.as_bytes())
+    }
+
+    // Helper function to match a single .gitignore at the work tree root
+    fn ignored(content: &str, path: &str, is_dir: bool) -> Option<bool> {
+        IgnoreList::parse(content.as_bytes(), ".gitignore", b"")
+            .last_match(path.as_bytes(), is_dir)
+            .map(|pattern| !pattern.negated)
+    }
+
+    #[test]
+    fn wildmatch_plain_wildcards() {
+        assert!(matches("foo", "foo"));
+        assert!(!matches("foo", "bar"));
+        assert!(matches("", ""));
+        assert!(matches("???", "foo"));
+        assert!(!matches("??", "foo"));
+        assert!(matches("*", "foo"));
+        assert!(matches("f*", "foo"));
+        assert!(!matches("*f", "foo"));
+        assert!(matches("*foo*", "foo"));
+        assert!(matches("*ob*a*r*", "foobar"));
+        assert!(matches("*ab", "aaaaaaabababab"));
+        // A single star stays within one path component
+        assert!(!matches("*", "foo/bar"));
+        assert!(!matches("foo?bar", "foo/bar"));
+        assert!(matches("foo/*", "foo/bar"));
+    }
+
+    #[test]
+    fn wildmatch_escapes() {
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "x"));
+        assert!(matches("*[al]?", "ball"));
+        assert!(matches("\\[ab]", "[ab]"));
+        assert!(matches("[[]ab]", "[ab]"));
+        // A trailing backslash escapes nothing, so the pattern matches nothing
+        assert!(!matches("foo\\", "foo"));
+        assert!(!matches("foo\\", "foo\\"));
+    }
+
+    #[test]
+    fn wildmatch_brackets() {
+        assert!(matches("[a-z]", "q"));
+        assert!(!matches("[a-z]", "Q"));
+        assert!(matches("[!a-z]", "Q"));
+        assert!(matches("[^a-z]", "Q"));
+        assert!(!matches("[!a-z]", "q"));
+        assert!(matches("[]]", "]"));
+        assert!(!matches("[!]]", "]"));
+        assert!(matches("[]-]", "-"));
+        assert!(matches("[a-]", "-"));
+        assert!(!matches("[/]", "/"));
+        assert!(matches("[[:alpha:]][[:digit:]][[:upper:]]", "a1B"));
+        assert!(!matches("[[:digit:][:upper:][:space:]]", "a"));
+        assert!(matches("[[:digit:][:upper:][:space:]]", " "));
+        assert!(matches("[[:xdigit:]]", "f"));
+        assert!(!matches("[[:nonsense:]]", "a"));
+        assert!(!matches("[abc", "a"));
+    }
+
+    #[test]
+    fn wildmatch_double_stars() {
+        assert!(matches("**", "foo/bar/baz"));
+        assert!(matches("**/foo", "foo"));
+        assert!(matches("**/foo", "a/b/foo"));
+        assert!(!matches("**/foo", "a/b/foox"));
+        assert!(matches("foo/**", "foo/bar/baz"));
+        assert!(!matches("foo/**", "foo"));
+        assert!(matches("a/**/b", "a/b"));
+        assert!(matches("a/**/b", "a/x/y/b"));
+        assert!(!matches("a/**/b", "a/xb"));
+        // Stars next to other characters are ordinary single stars
+        assert!(!matches("a**b", "a/x/b"));
+        assert!(matches("a**b", "axxb"));
+        assert!(matches("**/*.o", "deep/dir/file.o"));
+    }
+
+    #[test]
+    fn parses_comments_blanks_and_escapes() {
+        let list = IgnoreList::parse(
+            b"# comment\n\n\\#hash\n\\!bang\ntrailing   \nkept\\ \n",
+            ".gitignore",
+            b"",
+        );
+        let texts: Vec<&[u8]> = list.patterns.iter().map(|p| p.text.as_slice()).collect();
+        assert_eq!(
+            texts,
+            [&b"\\#hash"[..], b"\\!bang", b"trailing", b"kept\\ "]
+        );
+        assert_eq!(list.patterns[2].line_number, 5);
+
+        assert_eq!(ignored("\\#hash", "#hash", false), Some(true));
+        assert_eq!(ignored("\\!bang", "!bang", false), Some(true));
+        assert_eq!(ignored("kept\\ ", "kept ", false), Some(true));
+        assert_eq!(ignored("trailing   ", "trailing", false), Some(true));
+    }
+
+    #[test]
+    fn last_matching_pattern_wins() {
+        let rules = "*.log\n!keep.log\n";
+        assert_eq!(ignored(rules, "debug.log", false), Some(true));
+        assert_eq!(ignored(rules, "keep.log", false), Some(false));
+        assert_eq!(ignored(rules, "keep.txt", false), None);
+        assert_eq!(ignored("!keep.log\n*.log\n", "keep.log", false), Some(true));
+    }
+
+    #[test]
+    fn directory_only_patterns_skip_files() {
+        assert_eq!(ignored("build/", "build", true), Some(true));
+        assert_eq!(ignored("build/", "build", false), None);
+        assert_eq!(ignored("build/", "src/build", true), Some(true));
+    }
+
+    #[test]
+    fn slashes_anchor_patterns() {
+        // Without a slash, a pattern matches a name at any depth
+        assert_eq!(ignored("foo", "a/b/foo", false), Some(true));
+        // A leading or middle slash ties it to the .gitignore's directory
+        assert_eq!(ignored("/foo", "foo", false), Some(true));
+        assert_eq!(ignored("/foo", "a/foo", false), None);
+        assert_eq!(ignored("doc/*.txt", "doc/notes.txt", false), Some(true));
+        assert_eq!(ignored("doc/*.txt", "doc/sub/notes.txt", false), None);
+        assert_eq!(ignored("doc/*.txt", "x/doc/notes.txt", false), None);
+        assert_eq!(
+            ignored("doc/**/*.txt", "doc/sub/notes.txt", false),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn patterns_are_relative_to_their_directory() {
+        let list = IgnoreList::parse(b"/out\n*.tmp\n", "src/.gitignore", b"src/");
+        assert!(list.last_match(b"src/out", false).is_some());
+        assert!(list.last_match(b"out", false).is_none());
+        assert!(list.last_match(b"src/deep/x.tmp", false).is_some());
+        assert!(list.last_match(b"x.tmp", false).is_none());
+    }
+
+    #[test]
+    fn deeper_files_take_precedence() {
+        let tree = ScratchDir::new(
+            "ignore-precedence",
+            &[
+                (".gitignore", "*.log\nsecret\n"),
+                ("sub/.gitignore", "!*.log\n"),
+            ],
+        );
+        let exclude = IgnoreList::parse(b"*.bak\n!top.log\n", ".git/info/exclude", b"");
+        let mut rules = IgnoreRules::new(tree.path(), vec![exclude]);
+
+        assert!(rules.is_ignored(b"top.log", false).unwrap());
+        assert!(!rules.is_ignored(b"sub/kept.log", false).unwrap());
+        assert!(rules.is_ignored(b"sub/deeper/secret", false).unwrap());
+        // info/exclude only decides what no .gitignore mentions
+        assert!(rules.is_ignored(b"sub/x.bak", false).unwrap());
+        assert!(!rules.is_ignored(b"readme", false).unwrap());
+
+        let found = rules.matching(b"sub/kept.log", false).unwrap().unwrap();
+        assert_eq!(found.pattern.source, "sub/.gitignore");
+        assert_eq!(found.pattern.line_number, 1);
+    }
+
+    #[test]
+    fn excluded_directories_cannot_be_reopened() {
+        let tree = ScratchDir::new(
+            "ignore-reinclude",
+            &[(".gitignore", "build/\n!build/keep\n")],
+        );
+        let mut rules = IgnoreRules::new(tree.path(), Vec::new());
+
+        let found = rules.matching(b"build/keep", false).unwrap().unwrap();
+        assert_eq!(found.path, b"build");
+        assert!(!found.pattern.negated);
+        assert!(rules.is_ignored(b"build/keep", false).unwrap());
+    }
+}
@@ -0,0 +1,762 @@
+use crate::error::{Error, Result};
+use crate::object::ObjectId;
+use sha1::{Digest, Sha1};
+use std::fs::Metadata;
+use std::os::unix::fs::MetadataExt;
+
+// Flag bits of an index entry
+const FLAG_ASSUME_VALID: u16 = 0x8000;
+const FLAG_EXTENDED: u16 = 0x4000;
+const FLAG_STAGE_MASK: u16 = 0x3000;
+const FLAG_STAGE_SHIFT: u16 = 12;
+const FLAG_NAME_MASK: u16 = 0x0fff;
+
+// Extended flag bits, only stored by version 3 and later
+const EXTENDED_SKIP_WORKTREE: u16 = 0x4000;
+const EXTENDED_INTENT_TO_ADD: u16 = 0x2000;
+
+// Optional caches we do not keep up to date, so they are dropped rather than
+// written back stale
+const UNMAINTAINED_EXTENSIONS: [&[u8; 4]; 4] = [b"EOIE", b"IEOT", b"UNTR", b"FSMN"];
+
+// Helper function to build the error for malformed index files
+fn corrupt_index(message: impl Into<String>) -> Error {
+    Error::CorruptIndex(message.into())
+}
+
+// One staged file: its stat data, mode, blob and path
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub ctime_seconds: u32,
+    pub ctime_nanoseconds: u32,
+    pub mtime_seconds: u32,
+    pub mtime_nanoseconds: u32,
+    pub dev: u32,
+    pub ino: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+    pub id: ObjectId,
+    pub flags: u16,
+    pub extended_flags: u16,
+    pub path: Vec<u8>,
+}
+
+impl IndexEntry {
+    // Merge stage: 0 for a normal entry, 1-3 for the sides of a conflict
+    pub fn stage(&self) -> u16 {
+        (self.flags & FLAG_STAGE_MASK) >> FLAG_STAGE_SHIFT
+    }
+
+    pub fn set_stage(&mut self, stage: u16) {
+        self.flags =
+            (self.flags & !FLAG_STAGE_MASK) | ((stage << FLAG_STAGE_SHIFT) & FLAG_STAGE_MASK);
+    }
+
+    pub fn assume_valid(&self) -> bool {
+        self.flags & FLAG_ASSUME_VALID != 0
+    }
+
+    pub fn skip_worktree(&self) -> bool {
+        self.extended_flags & EXTENDED_SKIP_WORKTREE != 0
+    }
+
+    pub fn intent_to_add(&self) -> bool {
+        self.extended_flags & EXTENDED_INTENT_TO_ADD != 0
+    }
+
+    // An entry for a work tree file, with the stat data used to spot later changes
+    pub fn from_metadata(path: Vec<u8>, mode: u32, id: ObjectId, metadata: &Metadata) -> Self {
+        // The index stores 32-bit fields; larger values are truncated as git does
+        Self {
+            ctime_seconds: metadata.ctime() as u32,
+            ctime_nanoseconds: metadata.ctime_nsec() as u32,
+            mtime_seconds: metadata.mtime() as u32,
+            mtime_nanoseconds: metadata.mtime_nsec() as u32,
+            dev: metadata.dev() as u32,
+            ino: metadata.ino() as u32,
+            mode,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.size() as u32,
+            id,
+            path,
+            ..Self::default()
+        }
+    }
+
+    // Whether a file's stat data still matches what was recorded when it was staged
+    pub fn stat_matches(&self, metadata: &Metadata) -> bool {
+        self.mtime_seconds == metadata.mtime() as u32
+            && self.mtime_nanoseconds == metadata.mtime_nsec() as u32
+            && self.ctime_seconds == metadata.ctime() as u32
+            && self.ctime_nanoseconds == metadata.ctime_nsec() as u32
+            && self.ino == metadata.ino() as u32
+            && self.uid == metadata.uid()
+            && self.gid == metadata.gid()
+            && self.size == metadata.size() as u32
+    }
+
+    // The path, with invalid UTF-8 replaced
+    pub fn path_lossy(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(&self.path)
+    }
+
+    // Flags as stored: the name length saturates at 0xfff for long paths
+    fn stored_flags(&self) -> u16 {
+        let name_len = self.path.len().min(FLAG_NAME_MASK as usize) as u16;
+        let extended = match self.extended_flags {
+            0 => 0,
+            _ => FLAG_EXTENDED,
+        };
+        (self.flags & (FLAG_ASSUME_VALID | FLAG_STAGE_MASK)) | extended | name_len
+    }
+}
+
+// The TREE extension: tree names computed for directories of the index, so
+// write-tree only rehashes the directories whose entries changed
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheTree {
+    // The directory's name within its parent; empty for the root
+    pub name: Vec<u8>,
+    // Index entries the tree covers, or -1 when it must be recomputed
+    pub entry_count: i32,
+    // The tree's name, meaningful only while the entry count is valid
+    pub id: ObjectId,
+    pub subtrees: Vec<CacheTree>,
+}
+
+impl CacheTree {
+    pub fn is_valid(&self) -> bool {
+        self.entry_count >= 0
+    }
+
+    // The cached subdirectory with this name
+    pub fn subtree(&self, name: &[u8]) -> Option<&CacheTree> {
+        self.subtrees.iter().find(|subtree| subtree.name == name)
+    }
+
+    // The cached directory at a slash-separated path below this one
+    pub fn find(&self, path: &[u8]) -> Option<&CacheTree> {
+        path.split(|&b| b == b'/')
+            .filter(|component| !component.is_empty())
+            .try_fold(self, |tree, component| tree.subtree(component))
+    }
+
+    // Mark every directory leading to a changed path as needing a new tree
+    pub fn invalidate(&mut self, path: &[u8]) {
+        self.entry_count = -1;
+        if let Some(slash) = path.iter().position(|&b| b == b'/') {
+            let (name, rest) = (&path[..slash], &path[slash + 1..]);
+            if let Some(subtree) = self
+                .subtrees
+                .iter_mut()
+                .find(|subtree| subtree.name == name)
+            {
+                subtree.invalidate(rest);
+            }
+        }
+    }
+
+    fn parse(reader: &mut Reader<'_>) -> Result<Self> {
+        let invalid = || corrupt_index("Invalid TREE extension");
+
+        let name = reader.until_nul()?.to_vec();
+        let counts = reader.until(b'\n')?;
+        let (entry_count, subtree_count) = std::str::from_utf8(counts)
+            .ok()
+            .and_then(|counts| counts.split_once(' '))
+            .and_then(|(entries, subtrees)| Some((entries.parse().ok()?, subtrees.parse().ok()?)))
+            .ok_or_else(invalid)?;
+
+        let mut tree = Self {
+            name,
+            entry_count,
+            ..Self::default()
+        };
+        if tree.is_valid() {
+            tree.id = ObjectId::from_bytes(reader.take(ObjectId::LEN)?)?;
+        }
+        for _ in 0..subtree_count {
+            tree.subtrees.push(Self::parse(reader)?);
+        }
+        Ok(tree)
+    }
+
+    fn serialize(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.name);
+        out.push(0);
+        out.extend_from_slice(format!("{} {}\n", self.entry_count, self.subtrees.len()).as_bytes());
+        if self.is_valid() {
+            out.extend_from_slice(self.id.as_bytes());
+        }
+        for subtree in &self.subtrees {
+            subtree.serialize(out);
+        }
+    }
+}
+
+// An index extension kept as raw bytes, such as the resolve-undo data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexExtension {
+    pub signature: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+// The staging area, as stored in .git/index
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Index {
+    pub version: u32,
+    pub entries: Vec<IndexEntry>,
+    pub cache_tree: Option<CacheTree>,
+    pub extensions: Vec<IndexExtension>,
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Self {
+            version: 2,
+            entries: Vec::new(),
+            cache_tree: None,
+            extensions: Vec::new(),
+        }
+    }
+}
+
+// Cursor over the bytes of an index file
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or_else(|| corrupt_index("Index file is truncated"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    // Read up to a delimiter, consuming it
+    fn until(&mut self, delimiter: u8) -> Result<&'a [u8]> {
+        let len = self.data[self.pos..]
+            .iter()
+            .position(|&b| b == delimiter)
+            .ok_or_else(|| corrupt_index("Index field is not terminated"))?;
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len + 1;
+        Ok(bytes)
+    }
+
+    fn until_nul(&mut self) -> Result<&'a [u8]> {
+        self.until(0)
+    }
+
+    // Version 4 prefix lengths use the same encoding as ofs_delta base offsets
+    fn varint(&mut self) -> Result<usize> {
+        let mut byte = self.take(1)?[0];
+        let mut value = (byte & 0b0111_1111) as usize;
+        while byte & 0b1000_0000 != 0 {
+            byte = self.take(1)?[0];
+            value = value
+                .checked_add(1)
+                .filter(|value| value.leading_zeros() >= 7)
+                .map(|value| (value << 7) | (byte & 0b0111_1111) as usize)
+                .ok_or_else(|| corrupt_index("Index path prefix length overflows"))?;
+        }
+        Ok(value)
+    }
+}
+
+// Helper function to append a varint in the version 4 path prefix encoding
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    let mut bytes = vec![(value & 0b0111_1111) as u8];
+    value >>= 7;
+    while value != 0 {
+        value -= 1;
+        bytes.push(0b1000_0000 | (value & 0b0111_1111) as u8);
+        value >>= 7;
+    }
+    out.extend(bytes.iter().rev());
+}
+
+impl Index {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 12 + ObjectId::LEN {
+            return Err(corrupt_index("Index file is too short"));
+        }
+
+        // The trailing checksum covers everything before it
+        let (body, checksum) = data.split_at(data.len() - ObjectId::LEN);
+        if Sha1::digest(body).as_slice() != checksum {
+            return Err(corrupt_index("Index checksum mismatch"));
+        }
+
+        let mut reader = Reader { data: body, pos: 0 };
+        if reader.take(4)? != b"DIRC" {
+            return Err(corrupt_index("Index file has a bad signature"));
+        }
+        let version = reader.u32()?;
+        if !(2..=4).contains(&version) {
+            return Err(Error::Unsupported(format!(
+                "index file version {}",
+                version
+            )));
+        }
+        let count = reader.u32()? as usize;
+
+        let mut entries = Vec::with_capacity(count);
+        let mut previous_path: Vec<u8> = Vec::new();
+        for _ in 0..count {
+            let start = reader.pos;
+            let mut entry = IndexEntry {
+                ctime_seconds: reader.u32()?,
+                ctime_nanoseconds: reader.u32()?,
+                mtime_seconds: reader.u32()?,
+                mtime_nanoseconds: reader.u32()?,
+                dev: reader.u32()?,
+                ino: reader.u32()?,
+                mode: reader.u32()?,
+                uid: reader.u32()?,
+                gid: reader.u32()?,
+                size: reader.u32()?,
+                id: ObjectId::from_bytes(reader.take(ObjectId::LEN)?)?,
+                flags: reader.u16()?,
+                ..IndexEntry::default()
+            };
+
+            if entry.flags & FLAG_EXTENDED != 0 {
+                if version < 3 {
+                    return Err(corrupt_index("Extended flags in a version 2 index"));
+                }
+                entry.extended_flags = reader.u16()?;
+            }
+
+            if version == 4 {
+                // Each path drops some bytes from the end of the previous one
+                let strip = reader.varint()?;
+                let keep = previous_path
+                    .len()
+                    .checked_sub(strip)
+                    .ok_or_else(|| corrupt_index("Index path prefix is too long"))?;
+                let mut path = previous_path[..keep].to_vec();
+                path.extend_from_slice(reader.until_nul()?);
+                entry.path = path;
+            } else {
+                entry.path = reader.until_nul()?.to_vec();
+
+                // Entries are NUL padded to a multiple of eight bytes
+                let entry_len = reader.pos - start;
+                reader.take((8 - entry_len % 8) % 8)?;
+            }
+
+            previous_path.clone_from(&entry.path);
+            entries.push(entry);
+        }
+
+        let mut cache_tree = None;
+        let mut extensions = Vec::new();
+        while reader.pos < body.len() {
+            let signature: [u8; 4] = reader.take(4)?.try_into().unwrap_or_default();
+            let len = reader.u32()? as usize;
+            let data = reader.take(len)?.to_vec();
+
+            // Extensions starting with a lowercase letter must be understood
+            if signature[0].is_ascii_lowercase() {
+                return Err(Error::Unsupported(format!(
+                    "index extension '{}'",
+                    String::from_utf8_lossy(&signature)
+                )));
+            }
+            if UNMAINTAINED_EXTENSIONS.contains(&&signature) {
+                continue;
+            }
+            if &signature == b"TREE" {
+                let mut tree_reader = Reader {
+                    data: &data,
+                    pos: 0,
+                };
+                cache_tree = Some(CacheTree::parse(&mut tree_reader)?);
+                continue;
+            }
+            extensions.push(IndexExtension { signature, data });
+        }
+
+        Ok(Self {
+            version,
+            entries,
+            cache_tree,
+            extensions,
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        // Extended flags need version 3; version 4 already has them
+        let version = match self.version {
+            2 if self.entries.iter().any(|entry| entry.extended_flags != 0) => 3,
+            version => version,
+        };
+
+        let mut out = Vec::new();
+        out.extend_from_slice(b"DIRC");
+        out.extend_from_slice(&version.to_be_bytes());
+        out.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+
+        let mut previous_path: &[u8] = &[];
+        for entry in &self.entries {
+            let start = out.len();
+            for field in [
+                entry.ctime_seconds,
+                entry.ctime_nanoseconds,
+                entry.mtime_seconds,
+                entry.mtime_nanoseconds,
+                entry.dev,
+                entry.ino,
+                entry.mode,
+                entry.uid,
+                entry.gid,
+                entry.size,
+            ] {
+                out.extend_from_slice(&field.to_be_bytes());
+            }
+            out.extend_from_slice(entry.id.as_bytes());
+            out.extend_from_slice(&entry.stored_flags().to_be_bytes());
+            if entry.extended_flags != 0 {
+                out.extend_from_slice(&entry.extended_flags.to_be_bytes());
+            }
+
+            if version == 4 {
+                let common = previous_path
+                    .iter()
+                    .zip(&entry.path)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                write_varint(&mut out, previous_path.len() - common);
+                out.extend_from_slice(&entry.path[common..]);
+                out.push(0);
+            } else {
+                out.extend_from_slice(&entry.path);
+                let entry_len = out.len() - start;
+                out.resize(start + (entry_len + 8) / 8 * 8, 0);
+            }
+            previous_path = &entry.path;
+        }
+
+        if let Some(cache_tree) = &self.cache_tree {
+            let mut data = Vec::new();
+            cache_tree.serialize(&mut data);
+            out.extend_from_slice(b"TREE");
+            out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            out.extend_from_slice(&data);
+        }
+        for extension in &self.extensions {
+            out.extend_from_slice(&extension.signature);
+            out.extend_from_slice(&(extension.data.len() as u32).to_be_bytes());
+            out.extend_from_slice(&extension.data);
+        }
+
+        let checksum = Sha1::digest(&out);
+        out.extend_from_slice(&checksum);
+        out
+    }
+
+    // Position of an entry, or where it would be inserted, in (path, stage) order
+    fn search(&self, path: &[u8], stage: u16) -> std::result::Result<usize, usize> {
+        self.entries
+            .binary_search_by(|entry| (entry.path.as_slice(), entry.stage()).cmp(&(path, stage)))
+    }
+
+    // The entry for a path at a given merge stage
+    pub fn entry(&self, path: &[u8], stage: u16) -> Option<&IndexEntry> {
+        self.search(path, stage).ok().map(|pos| &self.entries[pos])
+    }
+
+    // Add an entry, replacing any with the same path and stage, keeping entries sorted;
+    // a stage 0 entry also resolves any conflict recorded for its path
+    pub fn add(&mut self, entry: IndexEntry) {
+        if entry.stage() == 0 {
+            let before = self.entries.len();
+            self.entries
+                .retain(|existing| existing.path != entry.path || existing.stage() == 0);
+            if self.entries.len() != before {
+                self.invalidate_cache_tree(&entry.path);
+            }
+        }
+
+        match self.search(&entry.path, entry.stage()) {
+            Ok(pos) => {
+                // Refreshing stat data leaves the cached trees valid
+                let existing = &self.entries[pos];
+                if existing.id != entry.id || existing.mode != entry.mode {
+                    self.invalidate_cache_tree(&entry.path);
+                }
+                self.entries[pos] = entry;
+            }
+            Err(pos) => {
+                self.invalidate_cache_tree(&entry.path);
+                self.entries.insert(pos, entry);
+            }
+        }
+    }
+
+    // Remove every stage of a path; returns whether anything was removed
+    pub fn remove(&mut self, path: &[u8]) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|entry| entry.path != path);
+        if self.entries.len() == before {
+            return false;
+        }
+        self.invalidate_cache_tree(path);
+        true
+    }
+
+    // Mark the cached trees leading to a changed path as stale
+    fn invalidate_cache_tree(&mut self, path: &[u8]) {
+        if let Some(cache_tree) = &mut self.cache_tree {
+            cache_tree.invalidate(path);
+        }
+    }
+
+    // The raw data of an extension, such as b"REUC"
+    pub fn extension(&self, signature: &[u8; 4]) -> Option<&[u8]> {
+        self.extensions
+            .iter()
+            .find(|extension| &extension.signature == signature)
+            .map(|extension| extension.data.as_slice())
+    }
+
+    // Drop an extension, for instance a cache that changed entries made stale
+    pub fn remove_extension(&mut self, signature: &[u8; 4]) {
+        self.extensions
+            .retain(|extension| &extension.signature != signature);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Helper function to build a stage 0 entry whose fields are all distinct
+    fn entry(path: &str, seed: u32) -> IndexEntry {
+        IndexEntry {
+            ctime_seconds: seed,
+            ctime_nanoseconds: seed + 1,
+            mtime_seconds: seed + 2,
+            mtime_nanoseconds: seed + 3,
+            dev: seed + 4,
+            ino: seed + 5,
+            mode: 0o100644,
+            uid: seed + 6,
+            gid: seed + 7,
+            size: seed + 8,
+            id: ObjectId::hash_object("blob", path.as_bytes()),
+            path: path.as_bytes().to_vec(),
+            ..IndexEntry::default()
+        }
+    }
+
+    // Helper function to build an index from entries, keeping them sorted
+    fn index(version: u32, entries: Vec<IndexEntry>) -> Index {
+        let mut index = Index {
+            version,
+            ..Index::default()
+        };
+        for entry in entries {
+            index.add(entry);
+        }
+        index
+    }
+
+    #[test]
+    fn round_trips_every_version() {
+        for version in 2..=4 {
+            let mut conflicted = entry("dir/conflict", 40);
+            conflicted.set_stage(2);
+            let original = index(
+                version,
+                vec![
+                    entry("a", 10),
+                    entry("dir/file", 20),
+                    entry("dir/filed", 30),
+                    conflicted,
+                    entry("z", 50),
+                ],
+            );
+
+            let bytes = original.serialize();
+            let parsed = Index::parse(&bytes).unwrap();
+            assert_eq!(parsed.version, version);
+            assert_eq!(parsed.entries.len(), 5);
+            for (parsed, original) in parsed.entries.iter().zip(&original.entries) {
+                assert_eq!(parsed.path, original.path);
+                assert_eq!(parsed.id, original.id);
+                assert_eq!(parsed.stage(), original.stage());
+                assert_eq!(parsed.mtime_nanoseconds, original.mtime_nanoseconds);
+                assert_eq!(parsed.size, original.size);
+            }
+            assert_eq!(parsed.serialize(), bytes);
+        }
+    }
+
+    #[test]
+    fn pads_version_2_entries_to_eight_bytes() {
+        // 62 bytes of fixed fields, then the path and at least one NUL
+        for (path, len) in [
+            ("a", 64),
+            ("abcdefg", 72),
+            ("abcdefgh", 72),
+            ("abcdefghi", 72),
+        ] {
+            let bytes = index(2, vec![entry(path, 1)]).serialize();
+            assert_eq!(bytes.len(), 12 + len + ObjectId::LEN, "path {}", path);
+        }
+    }
+
+    #[test]
+    fn compresses_version_4_paths_against_the_previous_one() {
+        let bytes = index(4, vec![entry("dir/file", 1), entry("dir/other", 2)]).serialize();
+        let second = 12 + 62 + 1 + "dir/file".len() + 1;
+        // "dir/file" becomes "dir/other" by dropping "file" and adding "other"
+        assert_eq!(&bytes[second + 62..second + 62 + 7], b"\x04other\0");
+
+        let parsed = Index::parse(&bytes).unwrap();
+        assert_eq!(parsed.entries[1].path, b"dir/other");
+    }
+
+    #[test]
+    fn upgrades_to_version_3_for_extended_flags() {
+        let mut hidden = entry("hidden", 1);
+        hidden.extended_flags = EXTENDED_SKIP_WORKTREE;
+        let bytes = index(2, vec![hidden]).serialize();
+
+        let parsed = Index::parse(&bytes).unwrap();
+        assert_eq!(parsed.version, 3);
+        assert!(parsed.entries[0].skip_worktree());
+        assert!(!parsed.entries[0].intent_to_add());
+    }
+
+    #[test]
+    fn saturates_the_name_length_of_long_paths() {
+        let path = "x".repeat(5000);
+        let bytes = index(2, vec![entry(&path, 1)]).serialize();
+        let parsed = Index::parse(&bytes).unwrap();
+        assert_eq!(parsed.entries[0].flags & FLAG_NAME_MASK, FLAG_NAME_MASK);
+        assert_eq!(parsed.entries[0].path.len(), 5000);
+    }
+
+    #[test]
+    fn round_trips_the_cache_tree_and_unknown_extensions() {
+        let mut original = index(2, vec![entry("dir/file", 1), entry("top", 2)]);
+        original.cache_tree = Some(CacheTree {
+            name: Vec::new(),
+            entry_count: -1,
+            id: ObjectId::default(),
+            subtrees: vec![CacheTree {
+                name: b"dir".to_vec(),
+                entry_count: 1,
+                id: ObjectId::hash_object("tree", b""),
+                subtrees: Vec::new(),
+            }],
+        });
+        original.extensions.push(IndexExtension {
+            signature: *b"REUC",
+            data: b"resolve undo".to_vec(),
+        });
+
+        let parsed = Index::parse(&original.serialize()).unwrap();
+        assert_eq!(parsed.cache_tree, original.cache_tree);
+        assert_eq!(parsed.extension(b"REUC"), Some(&b"resolve undo"[..]));
+        let cache_tree = parsed.cache_tree.unwrap();
+        assert!(!cache_tree.is_valid());
+        assert!(cache_tree.find(b"dir").unwrap().is_valid());
+    }
+
+    #[test]
+    fn drops_caches_it_does_not_maintain() {
+        let mut original = index(2, vec![entry("a", 1)]);
+        original.extensions.push(IndexExtension {
+            signature: *b"UNTR",
+            data: b"stale".to_vec(),
+        });
+        let parsed = Index::parse(&original.serialize()).unwrap();
+        assert!(parsed.extensions.is_empty());
+    }
+
+    #[test]
+    fn rejects_damaged_files() {
+        let bytes = index(2, vec![entry("a", 1)]).serialize();
+
+        let mut flipped = bytes.clone();
+        flipped[20] ^= 1;
+        assert!(matches!(
+            Index::parse(&flipped),
+            Err(Error::CorruptIndex(_))
+        ));
+
+        assert!(matches!(
+            Index::parse(&bytes[..bytes.len() - 1]),
+            Err(Error::CorruptIndex(_))
+        ));
+
+        // Rewrite the version and fix up the checksum so only the version is wrong
+        let mut body = bytes[..bytes.len() - ObjectId::LEN].to_vec();
+        body[4..8].copy_from_slice(&5u32.to_be_bytes());
+        let checksum = Sha1::digest(&body);
+        body.extend_from_slice(&checksum);
+        assert!(matches!(Index::parse(&body), Err(Error::Unsupported(_))));
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for value in [
+            0,
+            1,
+            127,
+            128,
+            255,
+            16511,
+            16512,
+            1 << 20,
+            usize::MAX >> 8,
+            usize::MAX,
+        ] {
+            let mut out = Vec::new();
+            write_varint(&mut out, value);
+            let mut reader = Reader { data: &out, pos: 0 };
+            assert_eq!(reader.varint().unwrap(), value);
+            assert_eq!(reader.pos, out.len());
+        }
+    }
+
+    #[test]
+    fn rejects_varints_too_large_for_usize() {
+        // usize::MAX with one more digit after it
+        let mut out = Vec::new();
+        write_varint(&mut out, usize::MAX);
+        *out.last_mut().unwrap() |= 0b1000_0000;
+        out.push(0);
+        let mut reader = Reader { data: &out, pos: 0 };
+        assert!(matches!(reader.varint(), Err(Error::CorruptIndex(_))));
+
+        let mut long = vec![0xff; 12];
+        long.push(0);
+        let mut reader = Reader {
+            data: &long,
+            pos: 0,
+        };
+        assert!(matches!(reader.varint(), Err(Error::CorruptIndex(_))));
+    }
+}
@@ -0,0 +1,40 @@
+use flate2::Compression;
+use std::io::Write;
+
+pub mod clone;
+pub mod commit;
+pub mod config;
+pub mod error;
+pub mod ident;
+pub mod ignore;
+pub mod index;
+pub mod object;
+pub mod odb;
+pub mod pack;
+pub mod refs;
+pub mod repository;
+pub mod status;
+#[cfg(test)]
+mod test_util;
+
+pub use error::{Error, Result};
+pub use index::Index;
+pub use object::ObjectId;
+pub use repository::Repository;
+
+// Helper function to compress data with zlib
+pub fn compress_data(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    use flate2::write::ZlibEncoder;
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+// Helper function to create git object with header
+pub fn create_git_object(object_type: &str, content: &[u8]) -> Vec<u8> {
+    let header = format!("{} {}\0", object_type, content.len());
+    let mut object = Vec::new();
+    object.extend_from_slice(header.as_bytes());
+    object.extend_from_slice(content);
+    object
+}
@@ -1,809 +1,1350 @@
-use flate2::read::ZlibDecoder;
-use flate2::Compression;
-use sha1::{Digest, Sha1};
+use mygit::clone::clone_repository;
+use mygit::commit::CommitOptions;
+use mygit::config::{Config, ConfigFile, ConfigScope};
+use mygit::object::{Object, TreeEntry};
+use mygit::repository::{AddOptions, IndexChange, LsTreeOptions, RmOptions};
+use mygit::status::{Status, UntrackedFiles};
+use mygit::{Error, ObjectId, Repository};
 #[allow(unused_imports)]
 use std::env;
-#[allow(unused_imports)]
-use std::fs;
-use std::fs::File;
-use std::io::{Read, Write};
-use std::path::Path;
-
-// Helper function to generate object path from hash
-fn get_object_path(hash: &str) -> String {
-    format!(".git/objects/{}/{}", &hash[0..2], &hash[2..])
-}
+use std::io::{BufRead, Read, Write};
+use std::process;
 
-// Helper function to ensure object directory exists
-fn ensure_object_dir(hash: &str) -> std::io::Result<()> {
-    fs::create_dir_all(format!(".git/objects/{}", &hash[0..2]))
+// Helper function to report a command-line usage error the way git does
+fn usage(synopsis: &str) -> ! {
+    eprintln!("usage: {}", synopsis);
+    process::exit(129);
 }
 
-// Helper function to compress data with zlib
-fn compress_data(data: &[u8]) -> std::io::Result<Vec<u8>> {
-    use flate2::write::ZlibEncoder;
-    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-    encoder.write_all(data)?;
-    encoder.finish()
-}
-
-// Helper function to decompress data with zlib
-fn decompress_data(file_path: &str) -> std::io::Result<Vec<u8>> {
-    let file = File::open(file_path)?;
-    let mut decompressed_data = Vec::new();
-    let mut zlib = ZlibDecoder::new(file);
-    zlib.read_to_end(&mut decompressed_data)?;
-    Ok(decompressed_data)
-}
-
-// Helper function to decompress data from bytes
-fn decompress_data_from_bytes(data: &[u8]) -> std::io::Result<Vec<u8>> {
-    let mut decoder = ZlibDecoder::new(data);
-    let mut result = Vec::new();
-    decoder.read_to_end(&mut result)?;
-    Ok(result)
-}
-
-// Helper function to calculate SHA1 hash
-fn calculate_sha1(data: &[u8]) -> String {
-    let mut hasher = Sha1::new();
-    hasher.update(data);
-    let hash = hasher.finalize();
-    format!("{:x}", hash)
-}
-
-// Helper function to convert hex string to bytes
-fn hex_to_bytes(hex_string: &str) -> Vec<u8> {
-    let mut bytes = Vec::new();
-    for i in (0..hex_string.len()).step_by(2) {
-        let byte = u8::from_str_radix(&hex_string[i..i + 2], 16).unwrap();
-        bytes.push(byte);
+// Helper function to fetch a required positional argument
+fn required_arg<'a>(args: &'a [String], index: usize, synopsis: &str) -> &'a str {
+    match args.get(index) {
+        Some(arg) => arg,
+        None => usage(synopsis),
     }
-    bytes
-}
-
-// Helper function to create git object with header
-fn create_git_object(object_type: &str, content: &[u8]) -> Vec<u8> {
-    let header = format!("{} {}\0", object_type, content.len());
-    let mut object = Vec::new();
-    object.extend_from_slice(header.as_bytes());
-    object.extend_from_slice(content);
-    object
-}
-
-// Helper function to write object to git objects directory
-fn write_git_object(object_data: &[u8]) -> std::io::Result<String> {
-    let hash_string = calculate_sha1(object_data);
-    let compressed_data = compress_data(object_data)?;
-
-    ensure_object_dir(&hash_string)?;
-    let object_path = get_object_path(&hash_string);
-    let mut object_file = File::create(object_path)?;
-    object_file.write_all(&compressed_data)?;
-
-    Ok(hash_string)
 }
 
-// Helper function to write object to specific parent directory
-fn write_object_to_parent(
-    parent: &Path,
-    object_type: &str,
-    content: &[u8],
-) -> std::io::Result<String> {
-    let object_data = create_git_object(object_type, content);
-    let hash = calculate_sha1(&object_data);
-    let compressed = compress_data(&object_data)?;
-
-    let obj_dir = parent.join(".git/objects").join(&hash[0..2]);
-    fs::create_dir_all(&obj_dir)?;
-    fs::write(obj_dir.join(&hash[2..]), compressed)?;
-
-    Ok(hash)
+// Helper function to turn an error into the message git prints after "fatal: "
+fn fatal_message(error: &Error) -> String {
+    match error {
+        Error::InvalidObjectName(name) => format!("Not a valid object name {}", name),
+        Error::ObjectNotFound(id) => format!("Not a valid object name {}", id),
+        Error::UnexpectedObjectType { expected, .. } => format!("not a {} object", expected),
+        other => other.to_string(),
+    }
 }
 
-// Helper function to read object from parent directory
-fn read_object_from_parent(parent: &Path, sha: &str) -> std::io::Result<(String, Vec<u8>)> {
-    let obj_path = parent.join(".git/objects").join(&sha[0..2]).join(&sha[2..]);
-    let compressed = fs::read(obj_path)?;
-    let decompressed = decompress_data_from_bytes(&compressed)?;
+// Helper function to quote a path the way git does when it contains special or
+// non-ASCII bytes
+fn quote_path(path: &[u8]) -> String {
+    let needs_quoting = path
+        .iter()
+        .any(|&b| !(0x20..0x7f).contains(&b) || b == b'"' || b == b'\\');
+    if !needs_quoting {
+        return String::from_utf8_lossy(path).into_owned();
+    }
 
-    if let Some(null_pos) = decompressed.iter().position(|&b| b == 0) {
-        let header = String::from_utf8_lossy(&decompressed[0..null_pos]);
-        let parts: Vec<&str> = header.split(' ').collect();
-        if parts.len() >= 1 {
-            let obj_type = parts[0].to_string();
-            let content = decompressed[null_pos + 1..].to_vec();
-            return Ok((obj_type, content));
+    let mut quoted = String::from("\"");
+    for &b in path {
+        match b {
+            b'\x07' => quoted.push_str("\\a"),
+            b'\x08' => quoted.push_str("\\b"),
+            b'\t' => quoted.push_str("\\t"),
+            b'\n' => quoted.push_str("\\n"),
+            b'\x0b' => quoted.push_str("\\v"),
+            b'\x0c' => quoted.push_str("\\f"),
+            b'\r' => quoted.push_str("\\r"),
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b if !(0x20..0x7f).contains(&b) => quoted.push_str(&format!("\\{:03o}", b)),
+            b => quoted.push(b as char),
         }
     }
-
-    Err(std::io::Error::new(
-        std::io::ErrorKind::InvalidData,
-        "Invalid object format",
-    ))
+    quoted.push('"');
+    quoted
 }
 
-// Helper function to read file content
-fn read_file_content(file_path: &str) -> std::io::Result<Vec<u8>> {
-    fs::read(file_path)
-}
+// Helper function to express a path from the work tree root relative to the
+// current directory's prefix, as git shows paths to humans
+fn relative_path(path: &[u8], prefix: &[u8]) -> Vec<u8> {
+    let prefix_dirs: Vec<&[u8]> = prefix
+        .split(|&b| b == b'/')
+        .filter(|dir| !dir.is_empty())
+        .collect();
+
+    let mut rest = path;
+    let mut common = 0;
+    for dir in &prefix_dirs {
+        match rest.strip_prefix(*dir) {
+            Some(after) if after.first() == Some(&b'/') => {
+                rest = &after[1..];
+                common += 1;
+            }
+            _ => break,
+        }
+    }
 
-// Helper function for hash-object command
-fn hash_object_file(file_name: &str) -> std::io::Result<String> {
-    let file_data = read_file_content(file_name)?;
-    let git_object = create_git_object("blob", &file_data);
-    write_git_object(&git_object)
+    let mut relative = b"../".repeat(prefix_dirs.len() - common);
+    relative.extend_from_slice(rest);
+    if relative.is_empty() {
+        relative.extend_from_slice(b"./");
+    }
+    relative
 }
 
-// Helper function for cat-file command
-fn cat_file_object(object_hash: &str) -> std::io::Result<String> {
-    let object_path = get_object_path(object_hash);
-    let decompressed_data = decompress_data(&object_path)?;
-    let decompressed_string = String::from_utf8(decompressed_data).unwrap();
-    let object_parts: Vec<&str> = decompressed_string.split('\0').collect();
-    Ok(object_parts[1].to_string())
+// How status prints its report
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum StatusFormat {
+    Long,
+    Short,
+    PorcelainV1,
+    PorcelainV2,
 }
 
-// Helper function for ls-tree command
-fn list_tree_names(tree_hash: &str) -> std::io::Result<Vec<String>> {
-    let tree_path = get_object_path(tree_hash);
-    let decompressed_data = decompress_data(&tree_path)?;
-
-    let null_pos = decompressed_data.iter().position(|&b| b == 0).unwrap();
-    let mut entries_data = &decompressed_data[null_pos + 1..];
-    let mut names = Vec::new();
-
-    while !entries_data.is_empty() {
-        let null_pos = entries_data.iter().position(|&b| b == 0).unwrap();
-        let mode_name = String::from_utf8(entries_data[0..null_pos].to_vec()).unwrap();
-        let parts: Vec<&str> = mode_name.split(' ').collect();
-        let filename = parts[1];
-        names.push(filename.to_string());
-        entries_data = &entries_data[null_pos + 1 + 20..];
+// Helper function to print the long, human-oriented status report
+fn print_long_status(
+    status: &Status,
+    display: impl Fn(&[u8]) -> String,
+    merging: bool,
+    untracked_files: UntrackedFiles,
+    // commit shows the report when there is nothing to commit, in its own words
+    committing: bool,
+) {
+    match &status.branch {
+        Some(branch) => println!("On branch {}", branch),
+        None => println!(
+            "HEAD detached at {}",
+            status.head.map_or_else(String::new, |id| id.abbrev(7))
+        ),
+    }
+    if status.head.is_none() {
+        match committing {
+            true => println!("\nInitial commit\n"),
+            false => println!("\nNo commits yet\n"),
+        }
     }
 
-    Ok(names)
-}
-
-// Recursive function for write-tree
-fn write_tree_recursive(dir_path: &Path) -> std::io::Result<Vec<u8>> {
-    let mut tree_entries = Vec::new();
-
-    // Collect all entries (files and directories)
-    for entry in fs::read_dir(dir_path)? {
-        let entry = entry?;
-        let path = entry.path();
-        let file_name = path.file_name().unwrap().to_str().unwrap();
+    if merging {
+        if status.unmerged.is_empty() {
+            println!("All conflicts fixed but you are still merging.");
+            println!("  (use \"git commit\" to conclude merge)\n");
+        } else {
+            println!("You have unmerged paths.");
+            println!("  (fix conflicts and run \"git commit\")");
+            println!("  (use \"git merge --abort\" to abort the merge)\n");
+        }
+    }
 
-        // Skip .git directory
-        if file_name == ".git" {
-            continue;
+    // Outside a merge, conflicts come from commands that can be undone by unstaging
+    let unstage_hint = match status.head {
+        _ if merging => None,
+        Some(_) => Some("  (use \"git restore --staged <file>...\" to unstage)"),
+        None => Some("  (use \"git rm --cached <file>...\" to unstage)"),
+    };
+
+    if !status.unmerged.is_empty() {
+        println!("Unmerged paths:");
+        if let Some(hint) = unstage_hint {
+            println!("{}", hint);
+        }
+        let codes: Vec<&str> = status.unmerged.iter().map(|entry| entry.code()).collect();
+        let one_sided = codes.iter().any(|code| matches!(*code, "UD" | "DU"));
+        let not_deleted = codes
+            .iter()
+            .any(|code| !matches!(*code, "DD" | "UD" | "DU"));
+        match (codes.contains(&"DD"), one_sided) {
+            (false, false) => println!("  (use \"git add <file>...\" to mark resolution)"),
+            (true, false) if !not_deleted => {
+                println!("  (use \"git rm <file>...\" to mark resolution)")
+            }
+            _ => println!("  (use \"git add/rm <file>...\" as appropriate to mark resolution)"),
+        }
+        for entry in &status.unmerged {
+            let label = match entry.code() {
+                "DD" => "both deleted:",
+                "AU" => "added by us:",
+                "UD" => "deleted by them:",
+                "UA" => "added by them:",
+                "DU" => "deleted by us:",
+                "AA" => "both added:",
+                _ => "both modified:",
+            };
+            println!("\t{:<17}{}", label, display(&entry.path));
         }
+        println!();
+    }
 
-        if path.is_file() {
-            // Handle files
-            let file_data = read_file_content(&path.to_string_lossy())?;
-            let git_object = create_git_object("blob", &file_data);
-            let blob_hash_string = calculate_sha1(&git_object);
-            let hash_bytes = hex_to_bytes(&blob_hash_string);
+    let label = |change: u8| match change {
+        b'A' => "new file:",
+        b'D' => "deleted:",
+        b'T' => "typechange:",
+        _ => "modified:",
+    };
 
-            tree_entries.push((file_name.to_string(), "100644".to_string(), hash_bytes));
-        } else if path.is_dir() {
-            // Handle directories - recursively process subdirectory
-            let subdir_hash = write_tree_recursive(&path)?;
-            tree_entries.push((file_name.to_string(), "40000".to_string(), subdir_hash));
+    let staged: Vec<_> = status
+        .entries
+        .iter()
+        .filter(|entry| entry.staged != b' ')
+        .collect();
+    if !staged.is_empty() {
+        println!("Changes to be committed:");
+        if let Some(hint) = unstage_hint {
+            println!("{}", hint);
+        }
+        for entry in &staged {
+            println!("\t{:<12}{}", label(entry.staged), display(&entry.path));
         }
+        println!();
     }
 
-    // Sort entries by name (Git requirement)
-    tree_entries.sort_by(|a, b| a.0.cmp(&b.0));
-
-    // Build tree content
-    let mut tree_content = Vec::new();
-    for (name, mode, hash) in tree_entries {
-        tree_content.extend_from_slice(mode.as_bytes());
-        tree_content.push(b' ');
-        tree_content.extend_from_slice(name.as_bytes());
-        tree_content.push(0);
-        tree_content.extend_from_slice(&hash);
+    let unstaged: Vec<_> = status
+        .entries
+        .iter()
+        .filter(|entry| entry.unstaged != b' ')
+        .collect();
+    if !unstaged.is_empty() {
+        println!("Changes not staged for commit:");
+        match unstaged.iter().any(|entry| entry.unstaged == b'D') {
+            true => println!("  (use \"git add/rm <file>...\" to update what will be committed)"),
+            false => println!("  (use \"git add <file>...\" to update what will be committed)"),
+        }
+        println!("  (use \"git restore <file>...\" to discard changes in working directory)");
+        for entry in &unstaged {
+            let submodule = match entry.work_tree_mode {
+                Some(TreeEntry::MODE_GITLINK) if entry.unstaged == b'M' => " (new commits)",
+                _ => "",
+            };
+            println!(
+                "\t{:<12}{}{}",
+                label(entry.unstaged),
+                display(&entry.path),
+                submodule
+            );
+        }
+        println!();
     }
 
-    let tree_object = create_git_object("tree", &tree_content);
-    let hash_string = write_git_object(&tree_object)?;
-    let hash_bytes = hex_to_bytes(&hash_string);
-    Ok(hash_bytes)
-}
+    if !status.untracked.is_empty() {
+        println!("Untracked files:");
+        println!("  (use \"git add <file>...\" to include in what will be committed)");
+        for path in &status.untracked {
+            println!("\t{}", display(path));
+        }
+        println!();
+    }
 
-// Helper function for write-tree command
-fn write_tree() -> std::io::Result<String> {
-    let root_hash = write_tree_recursive(Path::new("."))?;
-    Ok(root_hash
-        .iter()
-        .map(|b| format!("{:02x}", b))
-        .collect::<String>())
+    // A merge with every conflict resolved can always be concluded
+    let committable = !staged.is_empty() || (merging && status.unmerged.is_empty());
+    if committable {
+        if untracked_files == UntrackedFiles::No {
+            println!("Untracked files not listed (use -u option to show untracked files)");
+        }
+    } else if !unstaged.is_empty() || !status.unmerged.is_empty() {
+        println!("no changes added to commit (use \"git add\" and/or \"git commit -a\")");
+    } else if !status.untracked.is_empty() {
+        println!("nothing added to commit but untracked files present (use \"git add\" to track)");
+    } else if status.head.is_none() {
+        println!("nothing to commit (create/copy files and use \"git add\" to track)");
+    } else if untracked_files == UntrackedFiles::No {
+        println!("nothing to commit (use -u to show untracked files)");
+    } else {
+        println!("nothing to commit, working tree clean");
+    }
 }
 
-// Helper function for commit-tree command
-fn create_commit(
-    tree_hash: &str,
-    parent_hash: Option<&str>,
-    message: &str,
-) -> std::io::Result<String> {
-    let mut commit_content = String::new();
-    commit_content.push_str(&format!("tree {}\n", tree_hash));
-
-    if let Some(parent) = parent_hash {
-        commit_content.push_str(&format!("parent {}\n", parent));
+// Helper function to print the short and porcelain status formats, one line per path
+fn print_short_status(
+    out: &mut impl Write,
+    status: &Status,
+    format: StatusFormat,
+    display: impl Fn(&[u8]) -> Vec<u8>,
+    show_branch: bool,
+    terminator: u8,
+) -> std::io::Result<()> {
+    let mode = |mode: Option<u32>| format!("{:06o}", mode.unwrap_or(0));
+    let id = |id: Option<ObjectId>| id.unwrap_or_default().to_hex();
+    let line = |out: &mut dyn Write, text: String, path: &[u8]| -> std::io::Result<()> {
+        out.write_all(text.as_bytes())?;
+        out.write_all(&display(path))?;
+        out.write_all(&[terminator])
+    };
+
+    if show_branch {
+        if format == StatusFormat::PorcelainV2 {
+            let oid = status
+                .head
+                .map_or_else(|| "(initial)".to_string(), |id| id.to_hex());
+            write!(out, "# branch.oid {}{}", oid, terminator as char)?;
+            let head = status.branch.as_deref().unwrap_or("(detached)");
+            write!(out, "# branch.head {}{}", head, terminator as char)?;
+        } else {
+            match (&status.branch, status.head) {
+                (Some(branch), Some(_)) => write!(out, "## {}", branch)?,
+                (Some(branch), None) => write!(out, "## No commits yet on {}", branch)?,
+                (None, _) => write!(out, "## HEAD (no branch)")?,
+            }
+            out.write_all(&[terminator])?;
+        }
     }
 
-    let author_info = "John Doe <john.doe@example.com> 1719158400 +0000";
-    commit_content.push_str(&format!("author {}\n", author_info));
-    commit_content.push_str(&format!("committer {}\n", author_info));
-    commit_content.push('\n'); // Empty line before message
-    commit_content.push_str(message);
-    commit_content.push('\n');
+    // Conflicts and changes are listed together in path order
+    let mut entries = status.entries.iter().peekable();
+    let mut unmerged = status.unmerged.iter().peekable();
+    loop {
+        let take_unmerged = match (entries.peek(), unmerged.peek()) {
+            (None, None) => break,
+            (Some(entry), Some(conflict)) => conflict.path < entry.path,
+            (None, Some(_)) => true,
+            (Some(_), None) => false,
+        };
+
+        if take_unmerged {
+            let Some(conflict) = unmerged.next() else {
+                break;
+            };
+            let text = match format {
+                StatusFormat::PorcelainV2 => {
+                    let [base, ours, theirs] = conflict.stages;
+                    format!(
+                        "u {} N... {} {} {} {} {} {} {} ",
+                        conflict.code(),
+                        mode(base.map(|stage| stage.0)),
+                        mode(ours.map(|stage| stage.0)),
+                        mode(theirs.map(|stage| stage.0)),
+                        mode(conflict.work_tree_mode),
+                        id(base.map(|stage| stage.1)),
+                        id(ours.map(|stage| stage.1)),
+                        id(theirs.map(|stage| stage.1)),
+                    )
+                }
+                _ => format!("{} ", conflict.code()),
+            };
+            line(out, text, &conflict.path)?;
+        } else {
+            let Some(entry) = entries.next() else {
+                break;
+            };
+            let text = match format {
+                StatusFormat::PorcelainV2 => {
+                    let column = |change: u8| match change {
+                        b' ' => '.',
+                        change => change as char,
+                    };
+                    // Submodules flag whether their checked out commit moved
+                    let modes = [
+                        entry.head.map(|head| head.0),
+                        entry.index.map(|index| index.0),
+                    ];
+                    let gitlink = modes
+                        .into_iter()
+                        .chain([entry.work_tree_mode])
+                        .any(|mode| mode == Some(TreeEntry::MODE_GITLINK));
+                    let submodule = match (gitlink, entry.unstaged) {
+                        (false, _) => "N...",
+                        (true, b'M') => "SC..",
+                        (true, _) => "S...",
+                    };
+                    format!(
+                        "1 {}{} {} {} {} {} {} {} ",
+                        column(entry.staged),
+                        column(entry.unstaged),
+                        submodule,
+                        mode(entry.head.map(|head| head.0)),
+                        mode(entry.index.map(|index| index.0)),
+                        mode(entry.work_tree_mode),
+                        id(entry.head.map(|head| head.1)),
+                        id(entry.index.map(|index| index.1)),
+                    )
+                }
+                _ => format!("{}{} ", entry.staged as char, entry.unstaged as char),
+            };
+            line(out, text, &entry.path)?;
+        }
+    }
 
-    let commit_object = create_git_object("commit", commit_content.as_bytes());
-    write_git_object(&commit_object)
+    let untracked_prefix = match format {
+        StatusFormat::PorcelainV2 => "? ",
+        _ => "?? ",
+    };
+    for path in &status.untracked {
+        line(out, untracked_prefix.to_string(), path)?;
+    }
+    Ok(())
 }
 
-// Initialize repository structure
-fn init_repo(parent: &Path) -> std::io::Result<()> {
-    fs::create_dir_all(parent.join(".git"))?;
-    fs::create_dir_all(parent.join(".git/objects"))?;
-    fs::create_dir_all(parent.join(".git/refs"))?;
-    fs::create_dir_all(parent.join(".git/refs/heads"))?;
+const CAT_FILE_BATCH_USAGE: &str = "git cat-file (--batch | --batch-check) [--batch-all-objects]";
+
+// Helper function to write one cat-file --batch record, or "<name> missing";
+// names are resolved as revisions, like the object argument of cat-file
+fn write_batch_record(
+    out: &mut impl Write,
+    repo: &Repository,
+    name: &str,
+    with_contents: bool,
+) -> mygit::Result<()> {
+    let object_id = match repo.resolve_revision(name) {
+        Ok(object_id) => object_id,
+        Err(Error::InvalidObjectName(_)) => {
+            writeln!(out, "{} missing", name)?;
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+
+    if with_contents {
+        match repo.try_read_object(&object_id)? {
+            Some((object_type, content)) => {
+                writeln!(out, "{} {} {}", object_id, object_type, content.len())?;
+                out.write_all(&content)?;
+                out.write_all(b"\n")?;
+            }
+            None => writeln!(out, "{} missing", name)?,
+        }
+    } else {
+        match repo.try_read_object_header(&object_id)? {
+            Some((object_type, size)) => writeln!(out, "{} {} {}", object_id, object_type, size)?,
+            None => writeln!(out, "{} missing", name)?,
+        }
+    }
     Ok(())
 }
 
-// Parse variable size from delta
-fn parse_size(data: &[u8]) -> (usize, &[u8]) {
-    let mut size = (data[0] & 0b0111_1111) as usize;
-    let mut i = 1;
-    let mut offset = 7;
+// Helper function for cat-file --batch and --batch-check, reading names from stdin
+// unless every object was asked for
+fn cat_file_batch(repo: &Repository, with_contents: bool, all_objects: bool) -> mygit::Result<()> {
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
 
-    while data[i - 1] & 0b1000_0000 != 0 {
-        size += ((data[i] & 0b0111_1111) as usize) << offset;
-        offset += 7;
-        i += 1;
+    if all_objects {
+        for object_id in repo.all_object_ids()? {
+            write_batch_record(&mut out, repo, &object_id.to_hex(), with_contents)?;
+        }
+        out.flush()?;
+        return Ok(());
     }
 
-    (size, &data[i..])
+    // Flush after every record so callers can interleave requests and replies
+    for line in std::io::stdin().lock().lines() {
+        let line = line?;
+        write_batch_record(&mut out, repo, line.trim_end_matches('\r'), with_contents)?;
+        out.flush()?;
+    }
+    Ok(())
 }
 
-// Apply delta to base content
-fn apply_delta(base_content: &[u8], delta_content: &[u8]) -> std::io::Result<Vec<u8>> {
-    let mut content = delta_content;
-
-    // Skip base and output sizes
-    let (_, remaining) = parse_size(content);
-    let (_, remaining) = parse_size(remaining);
-    content = remaining;
+fn run(args: &[String]) -> mygit::Result<()> {
+    let mut stdout = std::io::stdout().lock();
 
-    let mut target_content = Vec::new();
-
-    while !content.is_empty() {
-        let is_copy = content[0] & 0b1000_0000 != 0;
-
-        if is_copy {
-            let mut data_ptr = 1;
-            let mut offset = 0;
-            let mut size = 0;
+    match args[1].as_str() {
+        "init" => {
+            Repository::init(".")?;
+            println!("Initialized git directory");
+        }
 
-            // Read offset
-            for i in 0..4 {
-                if content[0] & (1 << i) != 0 {
-                    offset |= (content[data_ptr] as usize) << (i * 8);
-                    data_ptr += 1;
+        "cat-file" if args.get(2).is_some_and(|arg| arg.starts_with("--batch")) => {
+            let mut with_contents = None;
+            let mut all_objects = false;
+            for arg in &args[2..] {
+                match arg.as_str() {
+                    "--batch" => with_contents = Some(true),
+                    "--batch-check" => with_contents = Some(false),
+                    "--batch-all-objects" => all_objects = true,
+                    _ => usage(CAT_FILE_BATCH_USAGE),
                 }
             }
+            let Some(with_contents) = with_contents else {
+                usage(CAT_FILE_BATCH_USAGE);
+            };
+
+            cat_file_batch(&Repository::discover(".")?, with_contents, all_objects)?;
+        }
 
-            // Read size
-            for i in 0..3 {
-                if content[0] & (1 << (4 + i)) != 0 {
-                    size |= (content[data_ptr] as usize) << (i * 8);
-                    data_ptr += 1;
+        "cat-file" => {
+            let synopsis = "git cat-file (-t | -s | -e | -p) <object>";
+            let mode = required_arg(args, 2, synopsis);
+            let name = required_arg(args, 3, synopsis);
+            let repo = Repository::discover(".")?;
+            let object_id = repo.resolve_revision(name)?;
+
+            match mode {
+                "-t" => println!("{}", repo.read_object_header(&object_id)?.0),
+                "-s" => println!("{}", repo.read_object_header(&object_id)?.1),
+                "-e" => {
+                    if !repo.has_object(&object_id)? {
+                        process::exit(1);
+                    }
                 }
+                "-p" => stdout.write_all(&repo.cat_file_object(&object_id)?)?,
+                _ => usage(synopsis),
             }
+        }
 
-            if size == 0 {
-                size = 0x10000;
+        "hash-object" => {
+            let synopsis = "git hash-object [-t <type>] [-w] [--literally] \
+                            [--stdin | --stdin-paths | <file>...]";
+            let mut object_type = "blob";
+            let mut write = false;
+            let mut literally = false;
+            let mut from_stdin = false;
+            let mut stdin_paths = false;
+            let mut files = Vec::new();
+
+            let mut i = 2;
+            while i < args.len() {
+                match args[i].as_str() {
+                    "-w" => write = true,
+                    "-t" => {
+                        i += 1;
+                        object_type = required_arg(args, i, synopsis);
+                    }
+                    "--literally" => literally = true,
+                    "--stdin" => from_stdin = true,
+                    "--stdin-paths" => stdin_paths = true,
+                    "--" => {
+                        files.extend(args[i + 1..].iter().cloned());
+                        break;
+                    }
+                    flag if flag.starts_with('-') && flag.len() > 1 => usage(synopsis),
+                    file => files.push(file.to_string()),
+                }
+                i += 1;
             }
-
-            content = &content[data_ptr..];
-            if offset + size <= base_content.len() {
-                target_content.extend_from_slice(&base_content[offset..offset + size]);
+            if stdin_paths && (from_stdin || !files.is_empty()) {
+                usage(synopsis);
             }
-        } else {
-            let size = content[0] as usize;
-            let append = &content[1..size + 1];
-            content = &content[size + 1..];
-            target_content.extend_from_slice(append);
-        }
-    }
 
-    Ok(target_content)
-}
-
-// Render tree recursively to working directory
-fn render_tree(parent: &Path, dir: &Path, sha: &str) -> std::io::Result<()> {
-    fs::create_dir_all(dir)?;
-    let (_, tree_data) = read_object_from_parent(parent, sha)?;
-    let mut tree = tree_data.as_slice();
-
-    while !tree.is_empty() {
-        // Parse mode
-        let space_pos = tree.iter().position(|&b| b == b' ').unwrap();
-        let mode = &tree[..space_pos];
-        tree = &tree[space_pos + 1..];
-
-        // Parse name
-        let null_pos = tree.iter().position(|&b| b == 0).unwrap();
-        let name = String::from_utf8_lossy(&tree[..null_pos]);
-        tree = &tree[null_pos + 1..];
-
-        // Parse SHA
-        let sha = hex::encode(&tree[..20]);
-        tree = &tree[20..];
+            // Only writing needs a repository; hashing works anywhere
+            let repo = match write {
+                true => Some(Repository::discover(".")?),
+                false => None,
+            };
+            let hash = |content: &[u8]| -> mygit::Result<ObjectId> {
+                Object::check(object_type, content, literally)?;
+                match &repo {
+                    Some(repo) => repo.write_object(object_type, content),
+                    None => Ok(ObjectId::hash_object(object_type, content)),
+                }
+            };
 
-        match mode {
-            b"40000" => {
-                // Directory
-                render_tree(parent, &dir.join(name.as_ref()), &sha)?;
+            if from_stdin {
+                let mut content = Vec::new();
+                std::io::stdin().lock().read_to_end(&mut content)?;
+                writeln!(stdout, "{}", hash(&content)?)?;
             }
-            b"100644" => {
-                // Regular file
-                let (_, content) = read_object_from_parent(parent, &sha)?;
-                fs::write(dir.join(name.as_ref()), content)?;
+            for file in &files {
+                writeln!(stdout, "{}", hash(&std::fs::read(file)?)?)?;
             }
-            _ => {
-                // Skip other modes
+            if stdin_paths {
+                for line in std::io::stdin().lock().lines() {
+                    writeln!(stdout, "{}", hash(&std::fs::read(line?)?)?)?;
+                    stdout.flush()?;
+                }
             }
         }
-    }
 
-    Ok(())
-}
-
-// Helper function to parse default branch from refs response
-fn parse_default_branch(refs: &str) -> Option<String> {
-    for line in refs.lines() {
-        if line.contains("symref=HEAD:") {
-            if let Some(start) = line.find("symref=HEAD:") {
-                let symref_part = &line[start + 12..]; // Skip "symref=HEAD:"
-                if let Some(end) = symref_part.find(' ') {
-                    let branch_ref = &symref_part[..end];
-                    if let Some(branch_name) = branch_ref.strip_prefix("refs/heads/") {
-                        return Some(branch_name.to_string());
+        "ls-tree" => {
+            let synopsis = "git ls-tree [-r] [-t] [-l] [-z] [--name-only] <tree-ish> [<path>...]";
+            let mut options = LsTreeOptions::default();
+            let mut long = false;
+            let mut name_only = false;
+            let mut terminator = b'\n';
+            let mut operands = Vec::new();
+
+            for arg in &args[2..] {
+                match arg.as_str() {
+                    "--long" => long = true,
+                    "--name-only" | "--name-status" => name_only = true,
+                    "--" => {}
+                    flags if flags.starts_with('-') && !flags.starts_with("--") => {
+                        for flag in flags[1..].chars() {
+                            match flag {
+                                'r' => options.recursive = true,
+                                't' => options.show_trees = true,
+                                'l' => long = true,
+                                'z' => terminator = b'\0',
+                                _ => usage(synopsis),
+                            }
+                        }
                     }
-                } else {
-                    // If there's no space, take the rest of the line
-                    if let Some(branch_name) = symref_part.strip_prefix("refs/heads/") {
-                        return Some(branch_name.to_string());
+                    flag if flag.starts_with("--") => usage(synopsis),
+                    operand => operands.push(operand.to_string()),
+                }
+            }
+            if operands.is_empty() {
+                usage(synopsis);
+            }
+            let tree_ish = operands.remove(0);
+            options.paths = operands;
+
+            let repo = Repository::discover(".")?;
+            let tree_id = repo.peel_to_tree(&repo.resolve_revision(&tree_ish)?)?;
+            for (path, entry) in repo.ls_tree(&tree_id, &options)? {
+                if !name_only {
+                    let object_type = entry.object_type();
+                    write!(stdout, "{:06o} {} {}", entry.mode, object_type, entry.id)?;
+                    if long {
+                        let size = match object_type {
+                            "blob" => repo.read_object_header(&entry.id)?.1.to_string(),
+                            _ => "-".to_string(),
+                        };
+                        write!(stdout, " {:>7}", size)?;
                     }
+                    stdout.write_all(b"\t")?;
+                }
+                if terminator == b'\0' {
+                    stdout.write_all(&path)?;
+                } else {
+                    stdout.write_all(quote_path(&path).as_bytes())?;
                 }
+                stdout.write_all(&[terminator])?;
             }
         }
-    }
-    None
-}
 
-// Helper function to get head commit from refs response
-fn get_head_commit(refs: &str) -> Option<String> {
-    // Look for refs/heads/master or refs/heads/main line
-    for line in refs.lines() {
-        if line.ends_with("refs/heads/master") || line.ends_with("refs/heads/main") {
-            // Line format: "003f47b37f1a82bfe85f6d8df52b6258b75e4343b7fd refs/heads/master"
-            // Skip the length prefix (4 hex chars) and extract the SHA
-            if line.len() >= 48 {
-                // 4 (length) + 40 (SHA) + 4 (space + "refs")
-                let sha_part = &line[4..44]; // Extract exactly 40 characters after length prefix
-                if sha_part.len() == 40 && sha_part.chars().all(|c| c.is_ascii_hexdigit()) {
-                    return Some(sha_part.to_string());
+        "add" => {
+            let synopsis = "git add [-n] [-v] [-f] [-A | -u] [--] [<pathspec>...]";
+            let mut options = AddOptions::default();
+            let mut verbose = false;
+            let mut pathspecs = Vec::new();
+
+            let mut operands_only = false;
+            for arg in &args[2..] {
+                match arg.as_str() {
+                    _ if operands_only => pathspecs.push(arg.clone()),
+                    "-A" | "--all" => options.all = true,
+                    "-u" | "--update" => options.update = true,
+                    "-n" | "--dry-run" => options.dry_run = true,
+                    "-v" | "--verbose" => verbose = true,
+                    "-f" | "--force" => options.force = true,
+                    "--" => operands_only = true,
+                    flag if flag.starts_with('-') => usage(synopsis),
+                    pathspec => pathspecs.push(pathspec.to_string()),
                 }
             }
-        }
-    }
-
-    None
-}
+            if options.all && options.update {
+                usage(synopsis);
+            }
+            if pathspecs.is_empty() && !options.all && !options.update {
+                eprintln!("Nothing specified, nothing added.");
+                eprintln!("hint: Maybe you wanted to say 'git add .'?");
+                return Ok(());
+            }
 
-// Helper function to process ref delta objects
-fn process_ref_delta(
-    parent: &Path,
-    base_sha_bytes: &[u8],
-    delta_data: &[u8],
-) -> std::io::Result<()> {
-    let base_sha = hex::encode(base_sha_bytes);
+            let (changes, ignored) = Repository::discover(".")?.add_paths(&pathspecs, &options)?;
+            if options.dry_run || verbose {
+                for change in changes {
+                    match change {
+                        IndexChange::Add(path) => {
+                            println!("add '{}'", String::from_utf8_lossy(&path))
+                        }
+                        IndexChange::Remove(path) => {
+                            println!("remove '{}'", String::from_utf8_lossy(&path))
+                        }
+                    }
+                }
+            }
 
-    if let Ok((base_type, base_content)) = read_object_from_parent(parent, &base_sha) {
-        if let Ok(target_content) = apply_delta(&base_content, delta_data) {
-            write_object_to_parent(parent, &base_type, &target_content)?;
+            if !ignored.is_empty() {
+                eprintln!("The following paths are ignored by one of your .gitignore files:");
+                for path in &ignored {
+                    eprintln!("{}", String::from_utf8_lossy(path));
+                }
+                eprintln!("hint: Use -f if you really want to add them.");
+                eprintln!("hint: Turn this message off by running");
+                eprintln!("hint: \"git config advice.addIgnoredFile false\"");
+                process::exit(1);
+            }
         }
-    }
-
-    Ok(())
-}
-
-// Main clone function using improved logic
-fn clone_repository(repository_url: &str, local_path: &str) -> std::io::Result<()> {
-    let parent = Path::new(local_path);
-
-    // Create target directory structure
-    if let Some(parent_dir) = parent.parent() {
-        fs::create_dir_all(parent_dir)?;
-    }
-    fs::create_dir_all(parent)?;
-
-    init_repo(parent)?;
 
-    let client = reqwest::blocking::Client::new();
-
-    // Fetch refs with improved error handling
-    let smart_url = format!("{}/info/refs?service=git-upload-pack", repository_url);
-    // println!("Requesting refs from: {}", smart_url);
+        "rm" => {
+            let synopsis = "git rm [-f] [-n] [-r] [-q] [--cached] [--] <pathspec>...";
+            let mut options = RmOptions::default();
+            let mut quiet = false;
+            let mut pathspecs = Vec::new();
+
+            let mut operands_only = false;
+            for arg in &args[2..] {
+                match arg.as_str() {
+                    _ if operands_only => pathspecs.push(arg.clone()),
+                    "--cached" => options.cached = true,
+                    "-r" => options.recursive = true,
+                    "-f" | "--force" => options.force = true,
+                    "-n" | "--dry-run" => options.dry_run = true,
+                    "-q" | "--quiet" => quiet = true,
+                    "--" => operands_only = true,
+                    flag if flag.starts_with('-') => usage(synopsis),
+                    pathspec => pathspecs.push(pathspec.to_string()),
+                }
+            }
+            if pathspecs.is_empty() {
+                usage(synopsis);
+            }
 
-    let refs_response = client
-        .get(&smart_url)
-        .send()
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            let removed = Repository::discover(".")?.rm_paths(&pathspecs, &options)?;
+            if !quiet {
+                for path in removed {
+                    println!("rm '{}'", String::from_utf8_lossy(&path));
+                }
+            }
+        }
 
-    if !refs_response.status().is_success() {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("Failed to get refs: {}", refs_response.status()),
-        ));
-    }
+        "status" => {
+            let synopsis = "git status [-s] [-b] [-z] [--porcelain[=v1|v2]] [-u[<mode>]] [--long]";
+            let mut format = StatusFormat::Long;
+            let mut show_branch = false;
+            let mut null_terminated = false;
+            let mut untracked_files = UntrackedFiles::Normal;
+
+            // Helper function to parse the -u and --untracked-files modes
+            let untracked_mode = |mode: &str| match mode {
+                "" | "all" => UntrackedFiles::All,
+                "normal" => UntrackedFiles::Normal,
+                "no" => UntrackedFiles::No,
+                _ => usage(synopsis),
+            };
+
+            for arg in &args[2..] {
+                match arg.as_str() {
+                    "--short" => format = StatusFormat::Short,
+                    "--long" => format = StatusFormat::Long,
+                    "--porcelain" | "--porcelain=v1" => format = StatusFormat::PorcelainV1,
+                    "--porcelain=v2" => format = StatusFormat::PorcelainV2,
+                    "--branch" => show_branch = true,
+                    "--untracked-files" => untracked_files = UntrackedFiles::All,
+                    flag if flag.starts_with("--untracked-files=") => {
+                        untracked_files = untracked_mode(&flag["--untracked-files=".len()..])
+                    }
+                    flags if flags.starts_with('-') && !flags.starts_with("--") => {
+                        for (i, flag) in flags.char_indices().skip(1) {
+                            match flag {
+                                's' => format = StatusFormat::Short,
+                                'b' => show_branch = true,
+                                'z' => null_terminated = true,
+                                // -u takes the rest of the bundle as its mode
+                                'u' => {
+                                    untracked_files = untracked_mode(&flags[i + 1..]);
+                                    break;
+                                }
+                                _ => usage(synopsis),
+                            }
+                        }
+                    }
+                    _ => usage(synopsis),
+                }
+            }
 
-    let refs_bytes = refs_response
-        .bytes()
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-
-    let refs_data = String::from_utf8_lossy(&refs_bytes);
-    // eprintln!("Refs data: {}", refs_data);
-
-    let head_commit = get_head_commit(&refs_data)
-        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "No head commit found!"))?;
-
-    // Parse the default branch from symbolic ref
-    let default_branch = parse_default_branch(&refs_data).unwrap_or_else(|| "main".to_string());
-
-    // Create pack request
-    let pack_url = format!("{}/git-upload-pack", repository_url);
-    let pack_request = format!(
-        "0032want {}\n\
-         0000\
-         0009done\n",
-        head_commit
-    );
-
-    // eprintln!("Requesting packfile from: {}", pack_url);
-    let pack_response = client
-        .post(&pack_url)
-        .header("Content-Type", "application/x-git-upload-pack-request")
-        .header("Accept", "application/x-git-upload-pack-result")
-        .body(pack_request)
-        .send()
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-
-    if !pack_response.status().is_success() {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("Failed to get packfile: {}", pack_response.status()),
-        ));
-    }
+            // -z implies a machine format, which names paths from the work tree root
+            if null_terminated && format == StatusFormat::Long {
+                format = StatusFormat::PorcelainV1;
+            }
 
-    let pack_data = pack_response
-        .bytes()
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+            let repo = Repository::discover(".")?;
+            let status = repo.status(untracked_files)?;
+            let prefix = repo.pathspec_to_path(".")?;
+            let relative = !null_terminated && format != StatusFormat::PorcelainV1;
+            let display = |path: &[u8]| -> Vec<u8> {
+                let shown = match relative {
+                    true => relative_path(path, &prefix),
+                    false => path.to_vec(),
+                };
+                match null_terminated {
+                    true => shown,
+                    false => quote_path(&shown).into_bytes(),
+                }
+            };
+
+            if format == StatusFormat::Long {
+                let merging = repo.git_dir().join("MERGE_HEAD").exists();
+                let display = |path: &[u8]| String::from_utf8_lossy(&display(path)).into_owned();
+                print_long_status(&status, display, merging, untracked_files, false);
+            } else {
+                let terminator = if null_terminated { b'\0' } else { b'\n' };
+                print_short_status(
+                    &mut stdout,
+                    &status,
+                    format,
+                    display,
+                    show_branch,
+                    terminator,
+                )?;
+            }
+        }
 
-    // eprintln!("Received packfile of size: {} bytes", pack_data.len());
+        "check-ignore" => {
+            let synopsis =
+                "git check-ignore [-v] [-n] [-q] [-z] [--no-index] [--stdin] <pathname>...";
+            let mut verbose = false;
+            let mut non_matching = false;
+            let mut quiet = false;
+            let mut null_terminated = false;
+            let mut no_index = false;
+            let mut from_stdin = false;
+            let mut paths = Vec::new();
+
+            let mut operands_only = false;
+            for arg in &args[2..] {
+                match arg.as_str() {
+                    _ if operands_only => paths.push(arg.clone()),
+                    "-v" | "--verbose" => verbose = true,
+                    "-n" | "--non-matching" => non_matching = true,
+                    "-q" | "--quiet" => quiet = true,
+                    "-z" => null_terminated = true,
+                    "--no-index" => no_index = true,
+                    "--stdin" => from_stdin = true,
+                    "--" => operands_only = true,
+                    flag if flag.starts_with('-') => usage(synopsis),
+                    path => paths.push(path.to_string()),
+                }
+            }
 
-    if pack_data.is_empty() {
-        eprintln!("Received empty response from server");
-        eprintln!("Creating a minimal repository");
+            if from_stdin {
+                if !paths.is_empty() {
+                    eprintln!("fatal: cannot specify pathnames with --stdin");
+                    process::exit(128);
+                }
+                let mut input = Vec::new();
+                std::io::stdin().read_to_end(&mut input)?;
+                let delimiter = if null_terminated { b'\0' } else { b'\n' };
+                paths = input
+                    .split(|&b| b == delimiter)
+                    .filter(|path| !path.is_empty())
+                    .map(|path| String::from_utf8_lossy(path).into_owned())
+                    .collect();
+            } else if paths.is_empty() {
+                eprintln!("fatal: no path specified");
+                process::exit(128);
+            }
+            if quiet && (verbose || paths.len() > 1) {
+                eprintln!("fatal: --quiet is only valid with a single pathname");
+                process::exit(128);
+            }
+            if non_matching && !verbose {
+                eprintln!("fatal: --non-matching is only valid with --verbose");
+                process::exit(128);
+            }
 
-        // Write HEAD and refs using correct branch
-        fs::write(
-            parent.join(".git/HEAD"),
-            format!("ref: refs/heads/{}\n", default_branch),
-        )?;
-        let branch_ref = parent.join(".git/refs/heads").join(&default_branch);
-        if let Some(parent_dir) = branch_ref.parent() {
-            fs::create_dir_all(parent_dir)?;
-        }
-        fs::write(branch_ref, format!("{}\n", head_commit))?;
+            let repo = Repository::discover(".")?;
+            let index = repo.read_index()?;
+            let mut ignore = repo.ignore_rules()?;
+            let terminator = if null_terminated { '\0' } else { '\n' };
+
+            let mut any_ignored = false;
+            for path in &paths {
+                let relative = repo.pathspec_to_path(path)?;
+                let is_dir = path.ends_with('/') || std::path::Path::new(path).is_dir();
+
+                // Tracked files are never ignored unless the index is left out
+                let tracked = !no_index && index.entries.iter().any(|entry| entry.path == relative);
+                let found = match tracked {
+                    true => None,
+                    false => ignore.matching(&relative, is_dir)?,
+                };
+                // Without -v a re-including pattern just means "not ignored"
+                let found = found.filter(|found| verbose || !found.pattern.negated);
+                any_ignored |= found.is_some();
+                if quiet {
+                    continue;
+                }
 
-        return Ok(());
-    }
+                match (found, verbose) {
+                    (Some(found), true) if null_terminated => write!(
+                        stdout,
+                        "{}\0{}\0{}\0{}\0",
+                        found.pattern.source,
+                        found.pattern.line_number,
+                        String::from_utf8_lossy(&found.pattern.text),
+                        path
+                    )?,
+                    (Some(found), true) => writeln!(
+                        stdout,
+                        "{}:{}:{}\t{}",
+                        found.pattern.source,
+                        found.pattern.line_number,
+                        String::from_utf8_lossy(&found.pattern.text),
+                        quote_path(path.as_bytes())
+                    )?,
+                    (None, true) if non_matching && null_terminated => {
+                        write!(stdout, "\0\0\0{}\0", path)?
+                    }
+                    (None, true) if non_matching => {
+                        writeln!(stdout, "::\t{}", quote_path(path.as_bytes()))?
+                    }
+                    (Some(_), false) if null_terminated => {
+                        write!(stdout, "{}{}", path, terminator)?
+                    }
+                    (Some(_), false) => writeln!(stdout, "{}", quote_path(path.as_bytes()))?,
+                    (None, _) => {}
+                }
+            }
 
-    // Find packfile start
-    let mut pack_start = 0;
-    for (i, chunk) in pack_data.windows(4).enumerate() {
-        if chunk == b"PACK" {
-            pack_start = i;
-            break;
+            if !any_ignored {
+                stdout.flush()?;
+                process::exit(1);
+            }
         }
-    }
 
-    if pack_start == 0 && &pack_data[0..4] != b"PACK" {
-        eprintln!("Response: {}", String::from_utf8_lossy(&pack_data));
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            "Could not find packfile in response",
-        ));
-    }
+        "config" => {
+            let synopsis = "git config [--global | --system | --local | --file <file>] [--show-origin] (--get | --get-all | --list | --set | --add | --unset | --unset-all) [<name> [<value>]]";
+            let mut scope = None;
+            let mut file = None;
+            let mut show_origin = false;
+            let mut action = None;
+            let mut operands = Vec::new();
 
-    eprintln!("Packfile starts at offset: {}", pack_start);
+            let mut i = 2;
+            while i < args.len() {
+                let arg = args[i].as_str();
+                match arg {
+                    "--global" => scope = Some(ConfigScope::Global),
+                    "--system" => scope = Some(ConfigScope::System),
+                    "--local" => scope = Some(ConfigScope::Local),
+                    "-f" | "--file" if i + 1 < args.len() => {
+                        scope = Some(ConfigScope::File);
+                        file = Some(args[i + 1].clone());
+                        i += 1;
+                    }
+                    "--show-origin" => show_origin = true,
+                    "--get" | "--get-all" | "--set" | "--add" | "--unset" | "--unset-all" => {
+                        action = Some(arg)
+                    }
+                    "-l" | "--list" => action = Some("--list"),
+                    flag => match flag.strip_prefix("--file=") {
+                        Some(path) => {
+                            scope = Some(ConfigScope::File);
+                            file = Some(path.to_string());
+                        }
+                        None if flag.starts_with('-') => usage(synopsis),
+                        None => operands.push(flag),
+                    },
+                }
+                i += 1;
+            }
 
-    // Process packfile with improved logic
-    let pack_file_data = &pack_data[pack_start..];
-    if pack_file_data.len() < 12 {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "Packfile too short",
-        ));
-    }
+            // Without an action, one operand reads a setting and two write it
+            let action = match (action, operands.len()) {
+                (Some(action), _) => action,
+                (None, 1) => "--get",
+                (None, 2) => "--set",
+                _ => usage(synopsis),
+            };
+            let expected = match action {
+                "--list" => 0,
+                "--set" | "--add" => 2,
+                _ => 1,
+            };
+            if operands.len() != expected {
+                eprintln!("error: wrong number of arguments, should be {}", expected);
+                usage(synopsis);
+            }
 
-    // Check header
-    if &pack_file_data[0..4] != b"PACK" {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "Invalid packfile header",
-        ));
-    }
+            let repo = Repository::discover(".").ok();
+            let git_dir = repo.as_ref().map(|repo| repo.git_dir());
+            let in_repo = |option: &str| match git_dir {
+                Some(git_dir) => git_dir,
+                None => {
+                    eprintln!("fatal: {}", option);
+                    process::exit(128);
+                }
+            };
+
+            // The file that writes go to, and the files that reads look at
+            let files = match (scope, &file) {
+                (Some(ConfigScope::File), Some(file)) => {
+                    vec![(
+                        std::path::PathBuf::from(file),
+                        ConfigScope::File,
+                        file.clone(),
+                    )]
+                }
+                (Some(ConfigScope::Local), _) => {
+                    let git_dir = in_repo("--local can only be used inside a git repository");
+                    let (path, origin) = Config::local_path(git_dir);
+                    vec![(path, ConfigScope::Local, origin)]
+                }
+                (Some(ConfigScope::System), _) => {
+                    let path = Config::system_path();
+                    let origin = path.display().to_string();
+                    vec![(path, ConfigScope::System, origin)]
+                }
+                (Some(scope), _) => Config::files(git_dir)
+                    .into_iter()
+                    .filter(|(_, file_scope, _)| *file_scope == scope)
+                    .collect(),
+                (None, _) => Config::files(git_dir),
+            };
+
+            if matches!(action, "--get" | "--get-all" | "--list") {
+                // Includes are followed unless one file was asked for
+                let config = Config::load_files(&files, git_dir, scope.is_none())?;
+                let entries = match action {
+                    "--list" => config.entries().iter().collect(),
+                    _ => match config.get_all(operands[0]) {
+                        Ok(entries) => entries,
+                        Err(e @ Error::InvalidConfigKey(_)) => {
+                            eprintln!("error: {}", e);
+                            process::exit(1);
+                        }
+                        Err(e) => return Err(e),
+                    },
+                };
+                let entries = match action {
+                    "--get" => &entries[entries.len().saturating_sub(1)..],
+                    _ => &entries[..],
+                };
+                if entries.is_empty() && action != "--list" {
+                    process::exit(1);
+                }
 
-    // Check version
-    let version = u32::from_be_bytes([
-        pack_file_data[4],
-        pack_file_data[5],
-        pack_file_data[6],
-        pack_file_data[7],
-    ]);
-    if version != 2 {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            format!("Unsupported packfile version: {}", version),
-        ));
-    }
+                for entry in entries {
+                    if show_origin {
+                        write!(stdout, "file:{}\t", entry.origin)?;
+                    }
+                    match (action, &entry.value) {
+                        ("--list", Some(value)) => writeln!(stdout, "{}={}", entry.name(), value)?,
+                        ("--list", None) => writeln!(stdout, "{}", entry.name())?,
+                        (_, value) => writeln!(stdout, "{}", value.as_deref().unwrap_or(""))?,
+                    }
+                }
+                return Ok(());
+            }
 
-    // Get number of objects
-    let num_objects = u32::from_be_bytes([
-        pack_file_data[8],
-        pack_file_data[9],
-        pack_file_data[10],
-        pack_file_data[11],
-    ]);
-    println!("Processing packfile with {} objects", num_objects);
-
-    let mut data = &pack_file_data[12..];
-    let mut processed = 0;
-
-    while processed < num_objects && !data.is_empty() && data.len() > 20 {
-        println!("Processing object {}/{}", processed + 1, num_objects);
-
-        // Read object header
-        let first_byte = data[0];
-        let obj_type = (first_byte & 0b0111_0000) >> 4;
-        let mut size = (first_byte & 0b0000_1111) as u64;
-        let mut i = 1;
-        let mut shift = 4;
-
-        // Read variable length size
-        while data[i - 1] & 0b1000_0000 != 0 && i < data.len() {
-            size |= ((data[i] & 0b0111_1111) as u64) << shift;
-            shift += 7;
-            i += 1;
-        }
-
-        data = &data[i..];
-
-        println!("Object type: {}, size: {}", obj_type, size);
-
-        match obj_type {
-            1..=4 => {
-                // Regular objects (commit, tree, blob, tag)
-                let mut decoder = ZlibDecoder::new(data);
-                let mut decompressed = Vec::new();
-
-                if decoder.read_to_end(&mut decompressed).is_ok() {
-                    let consumed = decoder.total_in() as usize;
-                    data = &data[consumed..];
-
-                    let content = &decompressed[..size.min(decompressed.len() as u64) as usize];
-                    let type_name = match obj_type {
-                        1 => "commit",
-                        2 => "tree",
-                        3 => "blob",
-                        4 => "tag",
-                        _ => "unknown",
+            // Writes go to the repository config unless told otherwise
+            let (path, write_scope, origin) = match scope {
+                Some(ConfigScope::Global) => {
+                    let Some(path) = Config::global_write_path() else {
+                        eprintln!("fatal: $HOME not set");
+                        process::exit(128);
                     };
-
-                    if let Err(e) = write_object_to_parent(parent, type_name, content) {
-                        eprintln!("Error writing object: {}", e);
+                    let origin = path.display().to_string();
+                    (path, ConfigScope::Global, origin)
+                }
+                Some(ConfigScope::System) | Some(ConfigScope::File) => {
+                    let Some(file) = files.last().cloned() else {
+                        eprintln!("fatal: no config file to write to");
+                        process::exit(128);
+                    };
+                    file
+                }
+                _ => {
+                    let git_dir = in_repo("not in a git directory");
+                    let (path, origin) = Config::local_path(git_dir);
+                    (path, ConfigScope::Local, origin)
+                }
+            };
+
+            let name = operands[0];
+            let mut config_file = ConfigFile::read(&path, write_scope, &origin)?;
+            let edited = match action {
+                "--unset" | "--unset-all" => config_file
+                    .unset(name, action == "--unset-all")
+                    .map(|removed| removed > 0),
+                _ => config_file
+                    .set(name, operands[1], action == "--add")
+                    .map(|_| true),
+            };
+            match edited {
+                Ok(true) => config_file.write()?,
+                // Nothing to unset
+                Ok(false) => process::exit(5),
+                Err(e @ Error::InvalidConfigKey(_)) => {
+                    eprintln!("error: {}", e);
+                    process::exit(1);
+                }
+                Err(e @ Error::ConfigMultipleValues(_)) => {
+                    eprintln!("warning: {} has multiple values", name);
+                    if !action.starts_with("--unset") {
+                        eprintln!("error: {}", e);
+                        eprintln!(
+                            "       Use a regexp, --add or --replace-all to change {}.",
+                            name
+                        );
                     }
-                } else {
-                    eprintln!("Failed to decompress object {}", processed + 1);
-                    break;
+                    process::exit(5);
                 }
+                Err(e) => return Err(e),
             }
-            7 => {
-                // ref_delta
-                if data.len() < 20 {
-                    eprintln!("Not enough data for ref delta");
-                    break;
-                }
-
-                let base_sha_bytes = &data[..20];
-                data = &data[20..];
-
-                let mut decoder = ZlibDecoder::new(data);
-                let mut delta_content = Vec::new();
+        }
 
-                if decoder.read_to_end(&mut delta_content).is_ok() {
-                    let consumed = decoder.total_in() as usize;
-                    data = &data[consumed..];
+        "commit" => {
+            let synopsis =
+                "git commit [-q] [--amend] [--allow-empty] [-m <msg>...] [-F <file>] [--] ";
+            let mut options = CommitOptions::default();
+            let mut quiet = false;
+            let mut messages = Vec::new();
+            let mut message_file = None;
 
-                    if let Err(e) = process_ref_delta(parent, base_sha_bytes, &delta_content) {
-                        eprintln!("Error processing ref delta: {}", e);
+            let mut i = 2;
+            while i < args.len() {
+                let arg = args[i].as_str();
+                match arg {
+                    "--amend" => options.amend = true,
+                    "--allow-empty" => options.allow_empty = true,
+                    "-q" | "--quiet" => quiet = true,
+                    "-m" | "--message" | "-F" | "--file" => {
+                        let Some(value) = args.get(i + 1) else {
+                            match arg.strip_prefix("--") {
+                                Some(option) => {
+                                    eprintln!("error: option `{}' requires a value", option)
+                                }
+                                None => eprintln!("error: switch `{}' requires a value", &arg[1..]),
+                            }
+                            usage(synopsis);
+                        };
+                        match arg {
+                            "-m" | "--message" => messages.push(value.clone()),
+                            _ => message_file = Some(value.clone()),
+                        }
+                        i += 1;
                     }
-                } else {
-                    eprintln!("Failed to decompress ref delta {}", processed + 1);
-                    break;
+                    flag if flag.starts_with("--message=") => {
+                        messages.push(flag["--message=".len()..].to_string())
+                    }
+                    flag if flag.starts_with("--file=") => {
+                        message_file = Some(flag["--file=".len()..].to_string())
+                    }
+                    flag if flag.starts_with("-m") => messages.push(flag[2..].to_string()),
+                    flag if flag.starts_with("-F") => message_file = Some(flag[2..].to_string()),
+                    _ => usage(synopsis),
                 }
+                i += 1;
             }
-            6 => {
-                // ofs_delta - skip for now
-                eprintln!("Skipping ofs_delta object");
-                let mut decoder = ZlibDecoder::new(data);
-                let mut temp = Vec::new();
-                if decoder.read_to_end(&mut temp).is_ok() {
-                    let consumed = decoder.total_in() as usize;
-                    data = &data[consumed..];
+
+            // Each -m is a paragraph of its own
+            let message = match (messages.is_empty(), message_file) {
+                (false, Some(_)) => {
+                    eprintln!("fatal: Option -m cannot be combined with -F");
+                    process::exit(128);
                 }
+                (false, None) => Some(messages.join("\n\n").into_bytes()),
+                (true, Some(file)) => {
+                    let mut content = Vec::new();
+                    match file.as_str() {
+                        "-" => std::io::stdin().read_to_end(&mut content).map(|_| ())?,
+                        file => content = std::fs::read(file)?,
+                    }
+                    Some(content)
+                }
+                (true, None) => None,
+            };
+
+            let repo = Repository::discover(".")?;
+            // Without an editor, only an amended commit can do without a message
+            if message.is_none() && !options.amend {
+                eprintln!("fatal: no commit message given; use -m or -F");
+                process::exit(128);
             }
-            _ => {
-                eprintln!("Unknown object type: {}", obj_type);
-                break;
+            let summary = match repo.commit(message.as_deref(), &options) {
+                Ok(summary) => summary,
+                Err(Error::NothingToCommit) => {
+                    let status = repo.status(UntrackedFiles::Normal)?;
+                    let prefix = repo.pathspec_to_path(".")?;
+                    let display = |path: &[u8]| quote_path(&relative_path(path, &prefix));
+                    print_long_status(&status, display, false, UntrackedFiles::Normal, true);
+                    process::exit(1);
+                }
+                Err(e @ Error::EmptyCommitMessage) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+                Err(e) => return Err(e),
+            };
+
+            if !quiet {
+                let branch = summary.branch.as_deref().unwrap_or("detached HEAD");
+                let root = if summary.root { " (root-commit)" } else { "" };
+                println!(
+                    "[{}{} {}] {}",
+                    branch,
+                    root,
+                    summary.id.abbrev(7),
+                    summary.subject
+                );
+                let (author, committer) = (&summary.author, &summary.committer);
+                if (&author.name, &author.email) != (&committer.name, &committer.email) {
+                    println!(" Author: {} <{}>", author.name, author.email);
+                }
+                // An amended commit keeps its original date, which is worth pointing out
+                if options.amend {
+                    println!(" Date: {}", author.date());
+                }
             }
         }
 
-        processed += 1;
+        "write-tree" => {
+            let synopsis = "git write-tree [--missing-ok] [--prefix=<prefix>/]";
+            let mut prefix = None;
+            let mut missing_ok = false;
+            for arg in &args[2..] {
+                match arg.as_str() {
+                    "--missing-ok" => missing_ok = true,
+                    flag => match flag.strip_prefix("--prefix=") {
+                        Some(dir) => prefix = Some(dir),
+                        None => usage(synopsis),
+                    },
+                }
+            }
 
-        if processed >= num_objects {
-            break;
+            let hash = Repository::discover(".")?.write_tree(prefix, missing_ok)?;
+            println!("{}", hash);
         }
-    }
-
-    // Write HEAD and refs using correct branch
-    fs::write(
-        parent.join(".git/HEAD"),
-        format!("ref: refs/heads/{}\n", default_branch),
-    )?;
-    let branch_ref = parent.join(".git/refs/heads").join(&default_branch);
-    if let Some(parent_dir) = branch_ref.parent() {
-        fs::create_dir_all(parent_dir)?;
-    }
-    fs::write(branch_ref, format!("{}\n", head_commit))?;
-
-    // Create working directory from HEAD commit
-    if let Err(e) = create_working_directory_from_commit(parent, &head_commit) {
-        eprintln!("Warning: Failed to create working directory: {}", e);
-    }
-
-    Ok(())
-}
-
-// Helper function to create working directory from commit
-fn create_working_directory_from_commit(parent: &Path, head_commit: &str) -> std::io::Result<()> {
-    println!("Creating working directory from commit {}", head_commit);
 
-    // Read the commit object
-    let (_, commit_data) = read_object_from_parent(parent, head_commit)?;
-    let commit_str = String::from_utf8_lossy(&commit_data);
-    println!("Commit data: {}", commit_str);
-
-    // Extract tree SHA from commit
-    let tree_sha = commit_str
-        .lines()
-        .find(|line| line.starts_with("tree "))
-        .and_then(|line| line.split_whitespace().nth(1))
-        .ok_or_else(|| {
-            std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                "Could not find tree SHA in commit",
-            )
-        })?;
+        "commit-tree" => {
+            let synopsis = "git commit-tree [(-p <parent>)...] [-S[<keyid>]] [(-m <message>)...] \
+                            [(-F <file>)...] <tree>";
+            let repo = Repository::discover(".")?;
+            let resolve = |name: &str| match repo.resolve_revision(name) {
+                Ok(id) => id,
+                Err(_) => {
+                    eprintln!("fatal: not a valid object name {}", name);
+                    process::exit(128);
+                }
+            };
 
-    println!("Root tree SHA: {}", tree_sha);
+            let mut trees = Vec::new();
+            let mut parents: Vec<ObjectId> = Vec::new();
+            let mut message: Option<Vec<u8>> = None;
+            // An empty key asks for the configured one
+            let mut sign_key: Option<&str> = None;
 
-    // Render the tree to working directory
-    render_tree(parent, parent, tree_sha)?;
+            let mut i = 2;
+            while i < args.len() {
+                let arg = args[i].as_str();
+                // The key is optional, so it only ever comes attached
+                let signing = match arg {
+                    "--gpg-sign" => Some(Some("")),
+                    "--no-gpg-sign" => Some(None),
+                    _ => arg
+                        .strip_prefix("-S")
+                        .or_else(|| arg.strip_prefix("--gpg-sign="))
+                        .map(Some),
+                };
+                if let Some(key) = signing {
+                    sign_key = key;
+                    i += 1;
+                    continue;
+                }
+                let (flag, attached) = match arg {
+                    "-p" | "-m" | "-F" => (arg, None),
+                    _ if arg
+                        .get(..2)
+                        .is_some_and(|flag| ["-p", "-m", "-F"].contains(&flag)) =>
+                    {
+                        (&arg[..2], Some(&arg[2..]))
+                    }
+                    _ if arg.starts_with('-') => usage(synopsis),
+                    tree => {
+                        trees.push(tree);
+                        i += 1;
+                        continue;
+                    }
+                };
+                let value = match attached {
+                    Some(value) => value,
+                    None => match args.get(i + 1) {
+                        Some(value) => {
+                            i += 1;
+                            value.as_str()
+                        }
+                        None => {
+                            eprintln!("error: switch `{}' requires a value", &flag[1..]);
+                            usage(synopsis);
+                        }
+                    },
+                };
+                i += 1;
+
+                if flag == "-p" {
+                    let parent = resolve(value);
+                    match parents.contains(&parent) {
+                        true => eprintln!("error: duplicate parent {} ignored", parent),
+                        false => parents.push(parent),
+                    }
+                    continue;
+                }
 
-    Ok(())
-}
+                // Every -m or -F starts a paragraph of its own; only -m text gets
+                // its line ended, file contents are taken as they are
+                let message = message.get_or_insert_with(Vec::new);
+                if !message.is_empty() {
+                    message.push(b'\n');
+                }
+                match (flag, value) {
+                    ("-m", text) => {
+                        message.extend_from_slice(text.as_bytes());
+                        if message.last().is_some_and(|&b| b != b'\n') {
+                            message.push(b'\n');
+                        }
+                    }
+                    (_, "-") => std::io::stdin().read_to_end(message).map(|_| ())?,
+                    (_, file) => message.extend(std::fs::read(file)?),
+                }
+            }
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
+            let tree_id = match trees.as_slice() {
+                [tree] => resolve(tree),
+                [] => usage(synopsis),
+                _ => {
+                    eprintln!("fatal: must give exactly one tree");
+                    process::exit(128);
+                }
+            };
+            // Without -m or -F the message is read from stdin as it is
+            let message = match message {
+                Some(message) => message,
+                None => {
+                    let mut message = Vec::new();
+                    std::io::stdin().read_to_end(&mut message)?;
+                    message
+                }
+            };
 
-    match args[1].as_str() {
-        "init" => {
-            fs::create_dir(".git").unwrap();
-            fs::create_dir(".git/objects").unwrap();
-            fs::create_dir(".git/refs").unwrap();
-            fs::write(".git/HEAD", "ref: refs/heads/main\n").unwrap();
-            println!("Initialized git directory");
+            let hash = repo.create_commit(&tree_id, &parents, &message, &[], sign_key)?;
+            print!("{}", hash);
         }
 
-        "cat-file" => {
-            let object_hash = &args[3];
-            match cat_file_object(object_hash) {
-                Ok(content) => print!("{}", content),
-                Err(e) => eprintln!("Error: {}", e),
-            }
-        }
+        "clone" => {
+            let synopsis = "git clone <repository> <directory>";
+            let repository_url = required_arg(args, 2, synopsis);
+            let local_path = required_arg(args, 3, synopsis);
 
-        "hash-object" => {
-            let file_name = &args[3];
-            match hash_object_file(file_name) {
-                Ok(hash) => print!("{}", hash),
-                Err(e) => eprintln!("Error: {}", e),
-            }
+            clone_repository(repository_url, local_path)?;
+            println!("Repository cloned successfully");
         }
 
-        "ls-tree" if args[2] == "--name-only" => {
-            let tree_hash = &args[3];
-            match list_tree_names(tree_hash) {
-                Ok(names) => {
-                    for name in names {
-                        println!("{}", name);
-                    }
-                }
-                Err(e) => eprintln!("Error: {}", e),
-            }
+        command => {
+            eprintln!("git: '{}' is not a git command.", command);
+            process::exit(1);
         }
+    }
 
-        "write-tree" => match write_tree() {
-            Ok(hash) => print!("{}", hash),
-            Err(e) => eprintln!("Error: {}", e),
-        },
-
-        "commit-tree" => {
-            let tree_hash = &args[2];
-            let mut parent_hash = None;
-            let mut message = String::new();
+    Ok(())
+}
 
-            let mut i = 3;
-            while i < args.len() {
-                match args[i].as_str() {
-                    "-p" if i + 1 < args.len() => {
-                        parent_hash = Some(args[i + 1].as_str());
-                        i += 2;
-                    }
-                    "-m" if i + 1 < args.len() => {
-                        message = args[i + 1].clone();
-                        i += 2;
-                    }
-                    _ => i += 1,
-                }
-            }
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("usage: git <command> [<args>]");
+        process::exit(1);
+    }
 
-            match create_commit(tree_hash, parent_hash, &message) {
-                Ok(hash) => print!("{}", hash),
-                Err(e) => eprintln!("Error: {}", e),
-            }
+    match run(&args) {
+        Ok(()) => {}
+        // Refusals to lose work and failed signing are plain errors in git,
+        // not fatal ones
+        Err(e @ (Error::RemovalRefused(_) | Error::SigningFailed)) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
         }
-
-        "clone" => {
-            let repository_url = &args[2];
-            let local_path = &args[3];
-
-            match clone_repository(repository_url, local_path) {
-                Ok(_) => println!("Repository cloned successfully"),
-                Err(e) => eprintln!("Error cloning repository: {}", e),
+        Err(e) => {
+            if let Error::IdentityUnknown { role, .. } = &e {
+                eprintln!("{} identity unknown\n", role);
+                eprintln!("*** Please tell me who you are.\n");
+                eprintln!("Run\n");
+                eprintln!("  git config --global user.email \"you@example.com\"");
+                eprintln!("  git config --global user.name \"Your Name\"\n");
+                eprintln!("to set your account's default identity.");
+                eprintln!("Omit --global to set the identity only in this repository.\n");
             }
+            eprintln!("fatal: {}", fatal_message(&e));
+            process::exit(128);
         }
-
-        _ => println!("unknown command: {}", args[1]),
     }
 }
@@ -0,0 +1,813 @@
+use crate::error::{Error, Result};
+use sha1::{Digest, Sha1};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+// Helper function to build the error for malformed objects
+fn corrupt_object(message: impl Into<String>) -> Error {
+    Error::CorruptObject(message.into())
+}
+
+// A SHA-1 object name; the default is git's all-zero null name
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ObjectId([u8; 20]);
+
+impl ObjectId {
+    // Number of bytes in a binary object name
+    pub const LEN: usize = 20;
+
+    // Number of hex digits in a full object name
+    pub const HEX_LEN: usize = 40;
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let bytes: [u8; Self::LEN] = bytes
+            .try_into()
+            .map_err(|_| Error::InvalidObjectName(hex::encode(bytes)))?;
+        Ok(Self(bytes))
+    }
+
+    pub fn from_hex(hex_string: &str) -> Result<Self> {
+        let invalid = || Error::InvalidObjectName(hex_string.to_string());
+
+        if hex_string.len() != Self::HEX_LEN {
+            return Err(invalid());
+        }
+        let mut bytes = [0u8; Self::LEN];
+        hex::decode_to_slice(hex_string, &mut bytes).map_err(|_| invalid())?;
+        Ok(Self(bytes))
+    }
+
+    // Hash an object's type and content the way git names it
+    pub fn hash_object(object_type: &str, content: &[u8]) -> Self {
+        let mut hasher = Sha1::new();
+        hasher.update(format!("{} {}\0", object_type, content.len()));
+        hasher.update(content);
+        Self(hasher.finalize().into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8; Self::LEN] {
+        &self.0
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+
+    // The first `len` hex digits, as shown by `git log --abbrev`
+    pub fn abbrev(&self, len: usize) -> String {
+        let mut hex_string = self.to_hex();
+        hex_string.truncate(len.clamp(4, Self::HEX_LEN));
+        hex_string
+    }
+
+    // Check whether an abbreviated hex name refers to this object
+    pub fn starts_with_hex(&self, prefix: &str) -> bool {
+        prefix.len() <= Self::HEX_LEN && self.to_hex().starts_with(&prefix.to_ascii_lowercase())
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl fmt::Debug for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ObjectId({})", self.to_hex())
+    }
+}
+
+impl FromStr for ObjectId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::from_hex(s)
+    }
+}
+
+// File contents
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Blob {
+    pub data: Vec<u8>,
+}
+
+impl Blob {
+    pub fn parse(content: &[u8]) -> Self {
+        Self {
+            data: content.to_vec(),
+        }
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+}
+
+// One "<mode> <name>\0<sha>" record of a tree
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TreeEntry {
+    pub mode: u32,
+    pub name: Vec<u8>,
+    pub id: ObjectId,
+}
+
+impl TreeEntry {
+    // Mode of a subdirectory entry
+    pub const MODE_TREE: u32 = 0o40000;
+
+    // Mode of a regular, non-executable file entry
+    pub const MODE_BLOB: u32 = 0o100644;
+
+    // Mode of an executable file entry
+    pub const MODE_EXECUTABLE: u32 = 0o100755;
+
+    // Mode of a symbolic link entry, whose blob holds the link target
+    pub const MODE_SYMLINK: u32 = 0o120000;
+
+    // Mode of a submodule entry, naming a commit in another repository
+    pub const MODE_GITLINK: u32 = 0o160000;
+
+    pub fn is_tree(&self) -> bool {
+        self.mode == Self::MODE_TREE
+    }
+
+    // Type of the object the entry points at, judged from the file type bits of its mode
+    pub fn object_type(&self) -> &'static str {
+        match self.mode & 0o170000 {
+            0o040000 => "tree",
+            0o160000 => "commit",
+            _ => "blob",
+        }
+    }
+
+    // The entry name, with invalid UTF-8 replaced
+    pub fn name_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.name)
+    }
+
+    // The mode as written in trees, without leading zeros
+    pub fn mode_string(&self) -> String {
+        format!("{:o}", self.mode)
+    }
+}
+
+// Helper function to order tree entries the way git's base_name_compare does:
+// a subdirectory sorts as if its name ended with '/'
+pub fn base_name_compare(name1: &[u8], mode1: u32, name2: &[u8], mode2: u32) -> Ordering {
+    let len = name1.len().min(name2.len());
+    match name1[..len].cmp(&name2[..len]) {
+        Ordering::Equal => {}
+        order => return order,
+    }
+
+    let next = |name: &[u8], mode: u32| match name.get(len) {
+        Some(&b) => b,
+        None if mode == TreeEntry::MODE_TREE => b'/',
+        None => 0,
+    };
+    next(name1, mode1).cmp(&next(name2, mode2))
+}
+
+// A directory listing
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Tree {
+    pub entries: Vec<TreeEntry>,
+}
+
+// Collects the entries of a tree in any order, rejecting names git would refuse,
+// and builds the tree in the order git hashes it
+#[derive(Clone, Debug, Default)]
+pub struct TreeBuilder {
+    entries: Vec<TreeEntry>,
+    names: HashSet<Vec<u8>>,
+}
+
+// Check that a tree entry name is a single safe path component: not empty, no
+// '/' or NUL, not "." or "..", and not .git in any case
+pub fn check_entry_name(name: &[u8]) -> Result<()> {
+    let reason = if name.is_empty() {
+        "empty name"
+    } else if name.contains(&b'/') {
+        "name contains '/'"
+    } else if name.contains(&0) {
+        "name contains NUL"
+    } else if name == b"." || name == b".." {
+        "name is a relative path component"
+    } else if name.eq_ignore_ascii_case(b".git") {
+        "name is reserved for the repository"
+    } else {
+        return Ok(());
+    };
+    Err(Error::InvalidTreeEntry {
+        name: String::from_utf8_lossy(name).into_owned(),
+        reason,
+    })
+}
+
+impl TreeBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Add an entry whose name must be unique within the tree
+    pub fn insert(&mut self, mode: u32, name: impl Into<Vec<u8>>, id: ObjectId) -> Result<()> {
+        let name = name.into();
+        check_entry_name(&name)?;
+        if self.names.contains(&name) {
+            return Err(Error::InvalidTreeEntry {
+                name: String::from_utf8_lossy(&name).into_owned(),
+                reason: "duplicate name",
+            });
+        }
+
+        self.names.insert(name.clone());
+        self.entries.push(TreeEntry { mode, name, id });
+        Ok(())
+    }
+
+    // The tree, with its entries sorted
+    pub fn build(mut self) -> Tree {
+        self.entries
+            .sort_by(|a, b| base_name_compare(&a.name, a.mode, &b.name, b.mode));
+        Tree {
+            entries: self.entries,
+        }
+    }
+}
+
+impl Tree {
+    pub fn parse(content: &[u8]) -> Result<Self> {
+        let mut entries = Vec::new();
+        let mut data = content;
+
+        while !data.is_empty() {
+            // Parse mode
+            let space_pos = data
+                .iter()
+                .position(|&b| b == b' ')
+                .ok_or_else(|| corrupt_object("Tree entry is missing its mode"))?;
+            let mode = std::str::from_utf8(&data[..space_pos])
+                .ok()
+                .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+                .ok_or_else(|| corrupt_object("Tree entry has an invalid mode"))?;
+            data = &data[space_pos + 1..];
+
+            // Parse name
+            let null_pos = data
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or_else(|| corrupt_object("Tree entry is missing its name"))?;
+            let name = data[..null_pos].to_vec();
+            data = &data[null_pos + 1..];
+
+            // Parse SHA
+            let id_bytes = data
+                .get(..ObjectId::LEN)
+                .ok_or_else(|| corrupt_object("Tree entry is truncated"))?;
+            let id = ObjectId::from_bytes(id_bytes)?;
+            data = &data[ObjectId::LEN..];
+
+            entries.push(TreeEntry { mode, name, id });
+        }
+
+        let tree = Self { entries };
+        tree.check_entries()?;
+        Ok(tree)
+    }
+
+    // Check that every name is safe and appears once, in the order git sorts
+    // entries; a name repeated as both file and directory could otherwise let a
+    // checkout write through a symlink
+    pub fn check_entries(&self) -> Result<()> {
+        let mut names = HashSet::new();
+        let mut previous: Option<&TreeEntry> = None;
+        for entry in &self.entries {
+            check_entry_name(&entry.name)?;
+            let sorted = previous.map_or(true, |previous| {
+                base_name_compare(&previous.name, previous.mode, &entry.name, entry.mode)
+                    == Ordering::Less
+            });
+            previous = Some(entry);
+            let reason = match (names.insert(entry.name.as_slice()), sorted) {
+                (false, _) => "duplicate name",
+                (true, false) => "entries are not sorted",
+                (true, true) => continue,
+            };
+            return Err(Error::InvalidTreeEntry {
+                name: entry.name_lossy().into_owned(),
+                reason,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut tree_content = Vec::new();
+        for entry in &self.entries {
+            tree_content.extend_from_slice(entry.mode_string().as_bytes());
+            tree_content.push(b' ');
+            tree_content.extend_from_slice(&entry.name);
+            tree_content.push(0);
+            tree_content.extend_from_slice(entry.id.as_bytes());
+        }
+        tree_content
+    }
+}
+
+// Header names with their raw values, in the order an object lists them
+type Headers = Vec<(String, Vec<u8>)>;
+
+// Split "key value" header lines from the message; continuation lines start with
+// a space. Values and the message stay bytes, since only the header names are
+// required to be text.
+fn parse_headers(content: &[u8]) -> Result<(Headers, Vec<u8>)> {
+    let (header_bytes, message) = match content.windows(2).position(|pair| pair == b"\n\n") {
+        Some(pos) => (&content[..pos], &content[pos + 2..]),
+        None => (content.strip_suffix(b"\n").unwrap_or(content), &[][..]),
+    };
+
+    let mut headers: Headers = Vec::new();
+    for line in header_bytes.split(|&b| b == b'\n') {
+        if let Some(continuation) = line.strip_prefix(b" ") {
+            let (_, value) = headers
+                .last_mut()
+                .ok_or_else(|| corrupt_object("Continuation line without a header"))?;
+            value.push(b'\n');
+            value.extend_from_slice(continuation);
+        } else {
+            let (key, value) = match line.iter().position(|&b| b == b' ') {
+                Some(pos) => (&line[..pos], &line[pos + 1..]),
+                None => (line, &[][..]),
+            };
+            let key = std::str::from_utf8(key)
+                .map_err(|_| corrupt_object("Object header name is not valid UTF-8"))?;
+            headers.push((key.to_string(), value.to_vec()));
+        }
+    }
+
+    Ok((headers, message.to_vec()))
+}
+
+// Helper function to read a header value that must be text, like an object name
+fn header_text(value: &[u8]) -> Result<&str> {
+    std::str::from_utf8(value).map_err(|_| corrupt_object("Object header is not valid UTF-8"))
+}
+
+// Write one header, indenting the continuation lines of multi-line values
+fn serialize_header(out: &mut Vec<u8>, key: &str, value: &[u8]) {
+    out.extend_from_slice(key.as_bytes());
+    out.push(b' ');
+    for &b in value {
+        out.push(b);
+        if b == b'\n' {
+            out.push(b' ');
+        }
+    }
+    out.push(b'\n');
+}
+
+// A snapshot of a tree with its history and authorship. Idents, extra header
+// values and the message are kept as bytes, since they need not be UTF-8.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Commit {
+    pub tree: ObjectId,
+    pub parents: Vec<ObjectId>,
+    pub author: Vec<u8>,
+    pub committer: Vec<u8>,
+    // Headers after committer, such as encoding, mergetag or gpgsig
+    pub extra_headers: Vec<(String, Vec<u8>)>,
+    pub message: Vec<u8>,
+}
+
+impl Commit {
+    pub fn parse(content: &[u8]) -> Result<Self> {
+        let (headers, message) = parse_headers(content)?;
+
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+        let mut extra_headers = Vec::new();
+
+        for (key, value) in headers {
+            match key.as_str() {
+                "tree" if tree.is_some() => {
+                    return Err(corrupt_object("Commit has more than one tree"))
+                }
+                "tree" => tree = Some(ObjectId::from_hex(header_text(&value)?)?),
+                "parent" => parents.push(ObjectId::from_hex(header_text(&value)?)?),
+                "author" if author.is_none() => author = Some(value),
+                "committer" if committer.is_none() => committer = Some(value),
+                _ => extra_headers.push((key, value)),
+            }
+        }
+
+        Ok(Self {
+            tree: tree.ok_or_else(|| corrupt_object("Commit has no tree"))?,
+            parents,
+            author: author.ok_or_else(|| corrupt_object("Commit has no author"))?,
+            committer: committer.ok_or_else(|| corrupt_object("Commit has no committer"))?,
+            extra_headers,
+            message,
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut commit_content = Vec::new();
+        serialize_header(&mut commit_content, "tree", self.tree.to_hex().as_bytes());
+        for parent in &self.parents {
+            serialize_header(&mut commit_content, "parent", parent.to_hex().as_bytes());
+        }
+        serialize_header(&mut commit_content, "author", &self.author);
+        serialize_header(&mut commit_content, "committer", &self.committer);
+        for (key, value) in &self.extra_headers {
+            serialize_header(&mut commit_content, key, value);
+        }
+        commit_content.push(b'\n'); // Empty line before message
+        commit_content.extend_from_slice(&self.message);
+        commit_content
+    }
+}
+
+// An annotated tag
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tag {
+    pub object: ObjectId,
+    pub object_type: String,
+    pub tag: Vec<u8>,
+    pub tagger: Option<Vec<u8>>,
+    pub extra_headers: Vec<(String, Vec<u8>)>,
+    pub message: Vec<u8>,
+}
+
+impl Tag {
+    pub fn parse(content: &[u8]) -> Result<Self> {
+        let (headers, message) = parse_headers(content)?;
+
+        let mut object = None;
+        let mut object_type = None;
+        let mut tag = None;
+        let mut tagger = None;
+        let mut extra_headers = Vec::new();
+
+        for (key, value) in headers {
+            match key.as_str() {
+                "object" if object.is_none() => {
+                    object = Some(ObjectId::from_hex(header_text(&value)?)?)
+                }
+                "type" if object_type.is_none() => {
+                    object_type = Some(header_text(&value)?.to_string())
+                }
+                "tag" if tag.is_none() => tag = Some(value),
+                "tagger" if tagger.is_none() => tagger = Some(value),
+                _ => extra_headers.push((key, value)),
+            }
+        }
+
+        Ok(Self {
+            object: object.ok_or_else(|| corrupt_object("Tag has no object"))?,
+            object_type: object_type.ok_or_else(|| corrupt_object("Tag has no type"))?,
+            tag: tag.ok_or_else(|| corrupt_object("Tag has no name"))?,
+            tagger,
+            extra_headers,
+            message,
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut tag_content = Vec::new();
+        serialize_header(&mut tag_content, "object", self.object.to_hex().as_bytes());
+        serialize_header(&mut tag_content, "type", self.object_type.as_bytes());
+        serialize_header(&mut tag_content, "tag", &self.tag);
+        if let Some(tagger) = &self.tagger {
+            serialize_header(&mut tag_content, "tagger", tagger);
+        }
+        for (key, value) in &self.extra_headers {
+            serialize_header(&mut tag_content, key, value);
+        }
+        tag_content.push(b'\n');
+        tag_content.extend_from_slice(&self.message);
+        tag_content
+    }
+}
+
+// Any parsed object
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Object {
+    Blob(Blob),
+    Tree(Tree),
+    Commit(Commit),
+    Tag(Tag),
+}
+
+impl Object {
+    pub fn parse(object_type: &str, content: &[u8]) -> Result<Self> {
+        match object_type {
+            "blob" => Ok(Object::Blob(Blob::parse(content))),
+            "tree" => Ok(Object::Tree(Tree::parse(content)?)),
+            "commit" => Ok(Object::Commit(Commit::parse(content)?)),
+            "tag" => Ok(Object::Tag(Tag::parse(content)?)),
+            _ => Err(corrupt_object(format!(
+                "Unknown object type: {}",
+                object_type
+            ))),
+        }
+    }
+
+    // Check content about to be stored as an object; `literally` accepts any type
+    // name and content, as hash-object --literally does
+    pub fn check(object_type: &str, content: &[u8], literally: bool) -> Result<()> {
+        if literally {
+            if object_type.is_empty() || object_type.contains([' ', '\0']) {
+                return Err(Error::InvalidObjectType(object_type.to_string()));
+            }
+            return Ok(());
+        }
+
+        match object_type {
+            "blob" | "tree" | "commit" | "tag" => Self::parse(object_type, content).map(|_| ()),
+            _ => Err(Error::InvalidObjectType(object_type.to_string())),
+        }
+    }
+
+    pub fn object_type(&self) -> &'static str {
+        match self {
+            Object::Blob(_) => "blob",
+            Object::Tree(_) => "tree",
+            Object::Commit(_) => "commit",
+            Object::Tag(_) => "tag",
+        }
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        match self {
+            Object::Blob(blob) => blob.serialize(),
+            Object::Tree(tree) => tree.serialize(),
+            Object::Commit(commit) => commit.serialize(),
+            Object::Tag(tag) => tag.serialize(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMPTY_BLOB: &str = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+    const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+    // Helper function to build an entry pointing at the empty blob or tree
+    fn entry(mode: u32, name: &str) -> TreeEntry {
+        let id = match mode {
+            TreeEntry::MODE_TREE => EMPTY_TREE,
+            _ => EMPTY_BLOB,
+        };
+        TreeEntry {
+            mode,
+            name: name.as_bytes().to_vec(),
+            id: ObjectId::from_hex(id).unwrap(),
+        }
+    }
+
+    // Helper function to check for an InvalidTreeEntry error with this reason
+    fn rejected<T>(result: Result<T>, expected: &str) -> bool {
+        matches!(result, Err(Error::InvalidTreeEntry { reason, .. }) if reason == expected)
+    }
+
+    #[test]
+    fn sorts_directories_as_if_they_ended_in_a_slash() {
+        let blob = TreeEntry::MODE_BLOB;
+        let tree = TreeEntry::MODE_TREE;
+        // '-' (0x2d) < '.' (0x2e) < '/' (0x2f) < '0' (0x30)
+        assert_eq!(
+            base_name_compare(b"foo-bar", blob, b"foo.c", blob),
+            Ordering::Less
+        );
+        assert_eq!(
+            base_name_compare(b"foo.c", blob, b"foo", tree),
+            Ordering::Less
+        );
+        assert_eq!(
+            base_name_compare(b"foo", tree, b"foo0", blob),
+            Ordering::Less
+        );
+        // As a file, "foo" sorts before everything it prefixes
+        assert_eq!(
+            base_name_compare(b"foo", blob, b"foo-bar", blob),
+            Ordering::Less
+        );
+        assert_eq!(
+            base_name_compare(b"foo", tree, b"foo", tree),
+            Ordering::Equal
+        );
+        assert_eq!(
+            base_name_compare(b"foo", tree, b"foo", blob),
+            Ordering::Greater
+        );
+    }
+
+    #[test]
+    fn builds_trees_in_git_order() {
+        let mut builder = TreeBuilder::new();
+        for (mode, name) in [
+            (TreeEntry::MODE_BLOB, "foo0"),
+            (TreeEntry::MODE_TREE, "foo"),
+            (TreeEntry::MODE_BLOB, "foo.c"),
+            (TreeEntry::MODE_BLOB, "foo-bar"),
+        ] {
+            let entry = entry(mode, name);
+            builder.insert(entry.mode, entry.name, entry.id).unwrap();
+        }
+        let tree = builder.build();
+
+        let names: Vec<_> = tree.entries.iter().map(|e| e.name_lossy()).collect();
+        assert_eq!(names, ["foo-bar", "foo.c", "foo", "foo0"]);
+        // The same entries fed to git mktree
+        assert_eq!(
+            ObjectId::hash_object("tree", &tree.serialize()).to_hex(),
+            "cf32a62ca8e0a8f089f98513ce70492b6259ecd8"
+        );
+        assert_eq!(Tree::parse(&tree.serialize()).unwrap(), tree);
+    }
+
+    #[test]
+    fn refuses_names_git_would_refuse() {
+        let id = ObjectId::from_hex(EMPTY_BLOB).unwrap();
+        for (name, reason) in [
+            ("", "empty name"),
+            (".", "name is a relative path component"),
+            ("..", "name is a relative path component"),
+            (".git", "name is reserved for the repository"),
+            (".GiT", "name is reserved for the repository"),
+            ("a/b", "name contains '/'"),
+            ("/", "name contains '/'"),
+            ("a\0b", "name contains NUL"),
+        ] {
+            let mut builder = TreeBuilder::new();
+            assert!(
+                rejected(builder.insert(TreeEntry::MODE_BLOB, name, id), reason),
+                "{:?}",
+                name
+            );
+        }
+
+        // Names that merely look like the reserved ones are fine
+        let mut builder = TreeBuilder::new();
+        for name in ["...", ".gitignore", "git"] {
+            builder.insert(TreeEntry::MODE_BLOB, name, id).unwrap();
+        }
+    }
+
+    #[test]
+    fn refuses_duplicate_names_whatever_their_mode() {
+        let id = ObjectId::from_hex(EMPTY_BLOB).unwrap();
+        let mut builder = TreeBuilder::new();
+        builder.insert(TreeEntry::MODE_BLOB, "a", id).unwrap();
+        let duplicate = builder.insert(TreeEntry::MODE_TREE, "a", id);
+        assert!(rejected(duplicate, "duplicate name"));
+    }
+
+    #[test]
+    fn parses_only_sorted_trees_without_duplicates() {
+        let serialize = |entries: Vec<TreeEntry>| Tree { entries }.serialize();
+
+        let unsorted = serialize(vec![
+            entry(TreeEntry::MODE_BLOB, "foo0"),
+            entry(TreeEntry::MODE_TREE, "foo"),
+        ]);
+        assert!(rejected(Tree::parse(&unsorted), "entries are not sorted"));
+
+        // A file and a directory of one name, in the order git would sort them
+        let duplicate = serialize(vec![
+            entry(TreeEntry::MODE_SYMLINK, "a"),
+            entry(TreeEntry::MODE_TREE, "a"),
+        ]);
+        assert!(rejected(Tree::parse(&duplicate), "duplicate name"));
+
+        let escaping = serialize(vec![entry(TreeEntry::MODE_TREE, "..")]);
+        assert!(rejected(
+            Tree::parse(&escaping),
+            "name is a relative path component"
+        ));
+    }
+
+    // Helper function to check that parsing and serializing changes no byte,
+    // so the object keeps the name git gave it
+    fn assert_round_trips(object_type: &str, content: &[u8], id: &str) {
+        assert_eq!(ObjectId::hash_object(object_type, content).to_hex(), id);
+        let object = Object::parse(object_type, content).unwrap();
+        assert_eq!(object.serialize(), content);
+    }
+
+    #[test]
+    fn round_trips_a_signed_commit() {
+        // Made by git commit-tree -S; the signature's blank line is a lone space
+        let content = concat!(
+            "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n",
+            "author a <a@x> 1700000000 +0000\n",
+            "committer a <a@x> 1700000000 +0000\n",
+            "gpgsig -----BEGIN PGP SIGNATURE-----\n",
+            " \n",
+            " iHoEABYIACIWIQS+zFViRDiQ+YD7KqA18ZzOB6l1IgUCatPMigQcYUB4AAoJEDXx\n",
+            " nM4HqXUiHTEA/iznU04vio4Czl4kuMBPza+5C5pJf+6+/09eDEfe6EPEAP0TfrZb\n",
+            " +DyRKoqNG7PdKl0KjZyEZQc8y7Nz74N8gcbFDA==\n",
+            " =Hz4O\n",
+            " -----END PGP SIGNATURE-----\n",
+            "\n",
+            "msg\n",
+        )
+        .as_bytes();
+        assert_round_trips(
+            "commit",
+            content,
+            "83e7a6356e97c8166283c91d3c101c9069b77a68",
+        );
+
+        let commit = Commit::parse(content).unwrap();
+        assert!(commit.parents.is_empty());
+        let [(key, signature)] = commit.extra_headers.as_slice() else {
+            panic!("expected one extra header, got {:?}", commit.extra_headers);
+        };
+        assert_eq!(key, "gpgsig");
+        assert!(signature.starts_with(b"-----BEGIN PGP SIGNATURE-----\n\niHoE"));
+        assert!(signature.ends_with(b"=Hz4O\n-----END PGP SIGNATURE-----"));
+        assert_eq!(commit.message, b"msg\n");
+    }
+
+    #[test]
+    fn round_trips_a_merge_with_a_latin1_message() {
+        // Made by git commit-tree -p -p with i18n.commitEncoding=ISO-8859-1
+        let mut content = concat!(
+            "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n",
+            "parent 0b41f1b13dc8502a28fb2912ae9f085eb69fc7e7\n",
+            "parent 16f3b7d839405b1a09494cd68da0efa6fcb6073f\n",
+            "author a <a@x> 1700000000 +0000\n",
+            "committer a <a@x> 1700000000 +0000\n",
+            "encoding ISO-8859-1\n",
+            "\n",
+        )
+        .as_bytes()
+        .to_vec();
+        content.extend_from_slice(b"Merge caf\xe9\n\n\xff\xfe body\n");
+        assert_round_trips(
+            "commit",
+            &content,
+            "6fb3e8a389a055d739d517bdc234f3b5cf568780",
+        );
+
+        let commit = Commit::parse(&content).unwrap();
+        let parents: Vec<_> = commit.parents.iter().map(ObjectId::to_hex).collect();
+        assert_eq!(
+            parents,
+            [
+                "0b41f1b13dc8502a28fb2912ae9f085eb69fc7e7",
+                "16f3b7d839405b1a09494cd68da0efa6fcb6073f"
+            ]
+        );
+        assert_eq!(
+            commit.extra_headers,
+            [("encoding".to_string(), b"ISO-8859-1".to_vec())]
+        );
+        assert_eq!(commit.message, b"Merge caf\xe9\n\n\xff\xfe body\n");
+    }
+
+    #[test]
+    fn round_trips_a_tag_without_a_tagger() {
+        // Tags from before git recorded taggers have none
+        let content = concat!(
+            "object 6fb3e8a389a055d739d517bdc234f3b5cf568780\n",
+            "type commit\n",
+            "tag v0.1\n",
+            "\n",
+            "old tag\n",
+        )
+        .as_bytes();
+        assert_round_trips("tag", content, "5fec70c929e25d9693de88f7b501eeec0a82cdef");
+
+        let tag = Tag::parse(content).unwrap();
+        assert_eq!(tag.object_type, "commit");
+        assert_eq!(tag.tag, b"v0.1");
+        assert_eq!(tag.tagger, None);
+        assert!(tag.extra_headers.is_empty());
+        assert_eq!(tag.message, b"old tag\n");
+    }
+
+    #[test]
+    fn refuses_commits_with_two_trees() {
+        let content = concat!(
+            "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n",
+            "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n",
+            "author a <a@x> 1700000000 +0000\n",
+            "committer a <a@x> 1700000000 +0000\n",
+            "\n",
+        );
+        assert!(matches!(
+            Commit::parse(content.as_bytes()),
+            Err(Error::CorruptObject(_))
+        ));
+    }
+}
@@ -0,0 +1,387 @@
+use crate::error::{Error, Result};
+use crate::object::ObjectId;
+use crate::pack::{self, PackIndex};
+use crate::{compress_data, create_git_object};
+use flate2::read::ZlibDecoder;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+// Storage for git objects, addressed by their SHA-1 name
+pub trait ObjectDatabase {
+    // Read an object's type and content, or None if this store lacks it
+    fn try_read(&self, id: &ObjectId) -> Result<Option<(String, Vec<u8>)>>;
+
+    // Read an object's type and size without necessarily loading its content
+    fn try_read_header(&self, id: &ObjectId) -> Result<Option<(String, usize)>>;
+
+    // Store an object and return its name
+    fn write(&self, object_type: &str, content: &[u8]) -> Result<ObjectId>;
+
+    // List the names of every object in the store, in no particular order
+    fn list(&self) -> Result<Vec<ObjectId>>;
+
+    // Check whether the store has an object
+    fn exists(&self, id: &ObjectId) -> Result<bool> {
+        Ok(self.try_read_header(id)?.is_some())
+    }
+
+    // Read an object, failing with ObjectNotFound if it is missing
+    fn read(&self, id: &ObjectId) -> Result<(String, Vec<u8>)> {
+        self.try_read(id)?.ok_or(Error::ObjectNotFound(*id))
+    }
+
+    // Read an object's type and size, failing with ObjectNotFound if it is missing
+    fn read_header(&self, id: &ObjectId) -> Result<(String, usize)> {
+        self.try_read_header(id)?.ok_or(Error::ObjectNotFound(*id))
+    }
+}
+
+// Helper function to split an inflated loose object into type, size and header length
+fn parse_loose_header(data: &[u8]) -> Result<(String, usize, usize)> {
+    let invalid = || Error::CorruptObject("invalid loose object header".to_string());
+
+    let null_pos = data.iter().position(|&b| b == 0).ok_or_else(invalid)?;
+    let header = std::str::from_utf8(&data[..null_pos]).map_err(|_| invalid())?;
+    let (object_type, size) = header.split_once(' ').ok_or_else(invalid)?;
+    let size = size.parse().map_err(|_| invalid())?;
+
+    Ok((object_type.to_string(), size, null_pos + 1))
+}
+
+// Zlib-compressed files under objects/xx/yyyy
+pub struct LooseObjectDatabase {
+    objects_dir: PathBuf,
+}
+
+impl LooseObjectDatabase {
+    pub fn new(objects_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            objects_dir: objects_dir.into(),
+        }
+    }
+
+    // Path of the file holding an object
+    fn object_path(&self, id: &ObjectId) -> PathBuf {
+        let hex = id.to_hex();
+        self.objects_dir.join(&hex[0..2]).join(&hex[2..])
+    }
+
+    // Helper function to create a read-only temporary file directly in objects/,
+    // named as git names its own so stray ones are recognizable
+    fn create_temp_file(&self) -> Result<(PathBuf, fs::File)> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        fs::create_dir_all(&self.objects_dir)?;
+        loop {
+            let name = format!(
+                "tmp_obj_{}_{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            );
+            let path = self.objects_dir.join(name);
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o444)
+                .open(&path)
+            {
+                Ok(file) => return Ok((path, file)),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    // Open an object file, or None if it does not exist
+    fn open(&self, id: &ObjectId) -> Result<Option<fs::File>> {
+        match fs::File::open(self.object_path(id)) {
+            Ok(file) => Ok(Some(file)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl ObjectDatabase for LooseObjectDatabase {
+    fn try_read(&self, id: &ObjectId) -> Result<Option<(String, Vec<u8>)>> {
+        let Some(file) = self.open(id)? else {
+            return Ok(None);
+        };
+
+        let mut decompressed = Vec::new();
+        ZlibDecoder::new(file).read_to_end(&mut decompressed)?;
+        let (object_type, _, header_len) = parse_loose_header(&decompressed)?;
+        Ok(Some((object_type, decompressed.split_off(header_len))))
+    }
+
+    fn try_read_header(&self, id: &ObjectId) -> Result<Option<(String, usize)>> {
+        let Some(file) = self.open(id)? else {
+            return Ok(None);
+        };
+
+        // Inflate only enough to see the "<type> <size>\0" header
+        let mut decoder = ZlibDecoder::new(file);
+        let mut header = Vec::new();
+        let mut chunk = [0u8; 64];
+        while !header.contains(&0) {
+            let read = decoder.read(&mut chunk)?;
+            if read == 0 {
+                break;
+            }
+            header.extend_from_slice(&chunk[..read]);
+        }
+
+        let (object_type, size, _) = parse_loose_header(&header)?;
+        Ok(Some((object_type, size)))
+    }
+
+    fn write(&self, object_type: &str, content: &[u8]) -> Result<ObjectId> {
+        let id = ObjectId::hash_object(object_type, content);
+
+        // Objects are immutable, so an existing file is already correct
+        let object_path = self.object_path(&id);
+        if object_path.exists() {
+            return Ok(id);
+        }
+
+        // Write under a temporary name and rename into place, so nobody ever
+        // sees a partly written object under its real name
+        let object_data = compress_data(&create_git_object(object_type, content))?;
+        let (temp_path, mut file) = self.create_temp_file()?;
+        let written = file
+            .write_all(&object_data)
+            .and_then(|_| file.sync_all())
+            .and_then(|_| match object_path.parent() {
+                Some(dir) => fs::create_dir_all(dir),
+                None => Ok(()),
+            })
+            .and_then(|_| fs::rename(&temp_path, &object_path));
+        if let Err(e) = written {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e.into());
+        }
+        Ok(id)
+    }
+
+    fn list(&self) -> Result<Vec<ObjectId>> {
+        let fanout = match fs::read_dir(&self.objects_dir) {
+            Ok(fanout) => fanout,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut ids = Vec::new();
+        for dir in fanout {
+            let dir = dir?;
+            let prefix = dir.file_name();
+            let Some(prefix) = prefix.to_str().filter(|p| p.len() == 2) else {
+                continue;
+            };
+            if !dir.file_type()?.is_dir() {
+                continue;
+            }
+
+            // Stray files such as temporaries are skipped, not reported as corrupt
+            for file in fs::read_dir(dir.path())? {
+                let file_name = file?.file_name();
+                let Some(rest) = file_name.to_str() else {
+                    continue;
+                };
+                if let Ok(id) = ObjectId::from_hex(&format!("{}{}", prefix, rest)) {
+                    ids.push(id);
+                }
+            }
+        }
+        Ok(ids)
+    }
+}
+
+// Read-only access to the .pack/.idx pairs under objects/pack; indexes are
+// loaded once and the directory is only scanned again when a lookup misses
+pub struct PackObjectDatabase {
+    pack_dir: PathBuf,
+    packs: Mutex<Vec<PackIndex>>,
+}
+
+impl PackObjectDatabase {
+    pub fn new(pack_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            pack_dir: pack_dir.into(),
+            packs: Mutex::new(Vec::new()),
+        }
+    }
+
+    // Helper function to run a lookup against the loaded packs, retrying once
+    // with any packs that appeared since they were loaded
+    fn lookup<T>(&self, find: impl Fn(&[PackIndex]) -> Result<Option<T>>) -> Result<Option<T>> {
+        let mut packs = self.packs.lock().unwrap_or_else(|e| e.into_inner());
+        match find(&packs)? {
+            Some(found) => Ok(Some(found)),
+            None if pack::load_new_indexes(&self.pack_dir, &mut packs)? => find(&packs),
+            None => Ok(None),
+        }
+    }
+}
+
+impl ObjectDatabase for PackObjectDatabase {
+    fn try_read(&self, id: &ObjectId) -> Result<Option<(String, Vec<u8>)>> {
+        self.lookup(|packs| pack::read_packed_object(packs, id))
+    }
+
+    fn try_read_header(&self, id: &ObjectId) -> Result<Option<(String, usize)>> {
+        self.lookup(|packs| pack::read_packed_header(packs, id))
+    }
+
+    fn write(&self, _object_type: &str, _content: &[u8]) -> Result<ObjectId> {
+        Err(Error::Unsupported(
+            "pack object database is read-only".to_string(),
+        ))
+    }
+
+    fn list(&self) -> Result<Vec<ObjectId>> {
+        let mut packs = self.packs.lock().unwrap_or_else(|e| e.into_inner());
+        pack::load_new_indexes(&self.pack_dir, &mut packs)?;
+        pack::list_packed_objects(&packs)
+    }
+}
+
+// Objects held in memory, for tests and scratch work
+#[derive(Default)]
+pub struct InMemoryObjectDatabase {
+    objects: Mutex<HashMap<ObjectId, (String, Vec<u8>)>>,
+}
+
+impl InMemoryObjectDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ObjectDatabase for InMemoryObjectDatabase {
+    fn try_read(&self, id: &ObjectId) -> Result<Option<(String, Vec<u8>)>> {
+        let objects = self.objects.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(objects.get(id).cloned())
+    }
+
+    fn try_read_header(&self, id: &ObjectId) -> Result<Option<(String, usize)>> {
+        let objects = self.objects.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(objects
+            .get(id)
+            .map(|(object_type, content)| (object_type.clone(), content.len())))
+    }
+
+    fn write(&self, object_type: &str, content: &[u8]) -> Result<ObjectId> {
+        let id = ObjectId::hash_object(object_type, content);
+        let mut objects = self.objects.lock().unwrap_or_else(|e| e.into_inner());
+        objects.insert(id, (object_type.to_string(), content.to_vec()));
+        Ok(id)
+    }
+
+    fn list(&self) -> Result<Vec<ObjectId>> {
+        let objects = self.objects.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(objects.keys().copied().collect())
+    }
+}
+
+// Several stores searched in order; writes go to the first one
+pub struct CompositeObjectDatabase {
+    backends: Vec<Box<dyn ObjectDatabase>>,
+}
+
+impl CompositeObjectDatabase {
+    pub fn new(backends: Vec<Box<dyn ObjectDatabase>>) -> Self {
+        Self { backends }
+    }
+
+    // Loose objects first, then packs, as laid out in a .git directory
+    pub fn open(git_dir: &Path) -> Self {
+        let objects_dir = git_dir.join("objects");
+        Self::new(vec![
+            Box::new(LooseObjectDatabase::new(&objects_dir)),
+            Box::new(PackObjectDatabase::new(objects_dir.join("pack"))),
+        ])
+    }
+}
+
+impl ObjectDatabase for CompositeObjectDatabase {
+    fn try_read(&self, id: &ObjectId) -> Result<Option<(String, Vec<u8>)>> {
+        for backend in &self.backends {
+            if let Some(object) = backend.try_read(id)? {
+                return Ok(Some(object));
+            }
+        }
+        Ok(None)
+    }
+
+    fn try_read_header(&self, id: &ObjectId) -> Result<Option<(String, usize)>> {
+        for backend in &self.backends {
+            if let Some(header) = backend.try_read_header(id)? {
+                return Ok(Some(header));
+            }
+        }
+        Ok(None)
+    }
+
+    fn write(&self, object_type: &str, content: &[u8]) -> Result<ObjectId> {
+        // Skip the write when any backend, such as a pack, already has the object
+        let id = ObjectId::hash_object(object_type, content);
+        if self.exists(&id)? {
+            return Ok(id);
+        }
+
+        match self.backends.first() {
+            Some(backend) => backend.write(object_type, content),
+            None => Err(Error::Unsupported(
+                "no object database to write to".to_string(),
+            )),
+        }
+    }
+
+    // Objects stored in several backends are listed once
+    fn list(&self) -> Result<Vec<ObjectId>> {
+        let mut ids = Vec::new();
+        for backend in &self.backends {
+            ids.extend(backend.list()?);
+        }
+        ids.sort();
+        ids.dedup();
+        Ok(ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::ScratchDir;
+
+    #[test]
+    fn writes_loose_objects_without_leaving_temporaries() {
+        let dir = ScratchDir::new("odb-loose-write", &[]);
+        let odb = LooseObjectDatabase::new(dir.join("objects"));
+
+        let id = odb.write("blob", b"hello\n").unwrap();
+        assert_eq!(id.to_hex(), "ce013625030ba8dba906f756967f9e9ca394464a");
+        // Writing it again finds the existing file
+        assert_eq!(odb.write("blob", b"hello\n").unwrap(), id);
+        assert_eq!(
+            odb.read(&id).unwrap(),
+            ("blob".to_string(), b"hello\n".to_vec())
+        );
+        assert_eq!(odb.list().unwrap(), [id]);
+
+        let mut names: Vec<_> = fs::read_dir(dir.join("objects"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["ce"]);
+
+        let mode = fs::metadata(odb.object_path(&id)).unwrap().permissions();
+        assert!(mode.readonly());
+    }
+}
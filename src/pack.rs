@@ -0,0 +1,1109 @@
+use crate::error::{Error, Result};
+use crate::object::ObjectId;
+use crate::odb::ObjectDatabase;
+use flate2::read::ZlibDecoder;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+// Offsets at or above this value go into the 64-bit offset table of an index
+const LARGE_OFFSET: u64 = 0x8000_0000;
+
+// Size of the version 2 index header plus its fanout table
+const INDEX_HEADER_LEN: u64 = 8 + 256 * 4;
+
+// Longest delta chain followed when reading a pack: git's pack-objects never
+// writes a deeper one, and ref deltas that name each other stop here
+const MAX_DELTA_DEPTH: usize = 4095;
+
+// Helper function to build an error for malformed packs
+fn corrupt_pack(message: impl Into<String>) -> Error {
+    Error::CorruptPack(message.into())
+}
+
+// Parse variable size from delta
+fn parse_size(data: &[u8]) -> Result<(usize, &[u8])> {
+    let mut size = 0usize;
+    let mut shift = 0u32;
+    let mut i = 0;
+
+    loop {
+        let byte = *data
+            .get(i)
+            .ok_or_else(|| corrupt_pack("Truncated delta size"))?;
+        let bits = ((byte & 0b0111_1111) as usize)
+            .checked_shl(shift)
+            .filter(|bits| bits >> shift == (byte & 0b0111_1111) as usize)
+            .ok_or_else(|| corrupt_pack("Delta size overflows"))?;
+        size |= bits;
+        shift += 7;
+        i += 1;
+        if byte & 0b1000_0000 == 0 {
+            break;
+        }
+    }
+
+    Ok((size, &data[i..]))
+}
+
+// Apply delta to base content
+fn apply_delta(base_content: &[u8], delta_content: &[u8]) -> Result<Vec<u8>> {
+    let truncated = || corrupt_pack("Truncated delta instruction");
+
+    let (base_size, remaining) = parse_size(delta_content)?;
+    let (target_size, mut content) = parse_size(remaining)?;
+    if base_size != base_content.len() {
+        return Err(corrupt_pack(format!(
+            "Delta base size {} does not match base of {} bytes",
+            base_size,
+            base_content.len()
+        )));
+    }
+
+    // The declared size is untrusted, so only preallocate what the delta can
+    // plausibly produce
+    let mut target_content =
+        Vec::with_capacity(target_size.min(delta_content.len().saturating_mul(0x10000)));
+
+    while let Some((&instruction, rest)) = content.split_first() {
+        content = rest;
+
+        let piece = if instruction & 0b1000_0000 != 0 {
+            let mut offset = 0;
+            let mut size = 0;
+
+            // Read offset
+            for i in 0..4 {
+                if instruction & (1 << i) != 0 {
+                    let (&byte, rest) = content.split_first().ok_or_else(truncated)?;
+                    offset |= (byte as usize) << (i * 8);
+                    content = rest;
+                }
+            }
+
+            // Read size
+            for i in 0..3 {
+                if instruction & (1 << (4 + i)) != 0 {
+                    let (&byte, rest) = content.split_first().ok_or_else(truncated)?;
+                    size |= (byte as usize) << (i * 8);
+                    content = rest;
+                }
+            }
+
+            if size == 0 {
+                size = 0x10000;
+            }
+
+            offset
+                .checked_add(size)
+                .and_then(|end| base_content.get(offset..end))
+                .ok_or_else(|| {
+                    corrupt_pack(format!(
+                        "Delta copies {} bytes at {} from a base of {} bytes",
+                        size,
+                        offset,
+                        base_content.len()
+                    ))
+                })?
+        } else if instruction == 0 {
+            // Opcode zero is reserved
+            return Err(corrupt_pack("Unexpected delta opcode 0"));
+        } else {
+            let size = instruction as usize;
+            let append = content.get(..size).ok_or_else(truncated)?;
+            content = &content[size..];
+            append
+        };
+
+        // Stop as soon as the result outgrows its declared size
+        if piece.len() > target_size - target_content.len() {
+            return Err(corrupt_pack(format!(
+                "Delta result exceeds its declared {} bytes",
+                target_size
+            )));
+        }
+        target_content.extend_from_slice(piece);
+    }
+
+    if target_content.len() != target_size {
+        return Err(corrupt_pack(format!(
+            "Delta result is {} bytes but declares {}",
+            target_content.len(),
+            target_size
+        )));
+    }
+
+    Ok(target_content)
+}
+
+// Map a pack entry type number to its object type name
+pub fn object_type_name(obj_type: u8) -> Option<&'static str> {
+    match obj_type {
+        1 => Some("commit"),
+        2 => Some("tree"),
+        3 => Some("blob"),
+        4 => Some("tag"),
+        _ => None,
+    }
+}
+
+// Parse a pack entry header into its type number, inflated size and header length
+pub fn parse_entry_header(data: &[u8]) -> Result<(u8, u64, usize)> {
+    let first_byte = *data
+        .first()
+        .ok_or_else(|| corrupt_pack("Truncated pack entry header"))?;
+    let obj_type = (first_byte & 0b0111_0000) >> 4;
+    let mut size = (first_byte & 0b0000_1111) as u64;
+    let mut i = 1;
+    let mut shift = 4;
+
+    // Read variable length size
+    while data[i - 1] & 0b1000_0000 != 0 {
+        let byte = *data
+            .get(i)
+            .ok_or_else(|| corrupt_pack("Truncated pack entry header"))?;
+        size |= ((byte & 0b0111_1111) as u64)
+            .checked_shl(shift)
+            .filter(|bits| bits >> shift == (byte & 0b0111_1111) as u64)
+            .ok_or_else(|| corrupt_pack("Pack entry size overflows"))?;
+        shift += 7;
+        i += 1;
+    }
+
+    Ok((obj_type, size, i))
+}
+
+// Inflate a zlib stream, returning the data and the number of compressed bytes read
+fn inflate_entry(data: &[u8]) -> Result<(Vec<u8>, usize)> {
+    let mut decoder = ZlibDecoder::new(data);
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed)?;
+    Ok((decompressed, decoder.total_in() as usize))
+}
+
+// Base of a delta entry, by object name or by offset within the pack
+enum DeltaBase {
+    Ref(ObjectId),
+    Ofs(usize),
+}
+
+// Delta entry whose base has not been resolved yet
+struct PendingDelta {
+    offset: usize,
+    base: DeltaBase,
+    delta: Vec<u8>,
+}
+
+// Objects resolved so far while indexing a packfile
+#[derive(Default)]
+struct PackObjects {
+    by_offset: HashMap<usize, (String, Vec<u8>)>,
+    offsets_by_id: HashMap<ObjectId, usize>,
+    crc32_by_offset: HashMap<usize, u32>,
+}
+
+impl PackObjects {
+    // Remember a resolved object under its name and as a potential delta base
+    fn insert(&mut self, offset: usize, object_type: &str, content: Vec<u8>) {
+        let id = ObjectId::hash_object(object_type, &content);
+        self.offsets_by_id.insert(id, offset);
+        self.by_offset
+            .insert(offset, (object_type.to_string(), content));
+    }
+
+    // Look up a delta base in the pack, falling back to the object store
+    fn find_base(
+        &self,
+        odb: &dyn ObjectDatabase,
+        base: &DeltaBase,
+    ) -> Result<Option<(String, Vec<u8>)>> {
+        match base {
+            DeltaBase::Ofs(offset) => Ok(self.by_offset.get(offset).cloned()),
+            DeltaBase::Ref(id) => match self.offsets_by_id.get(id) {
+                Some(offset) => Ok(self.by_offset.get(offset).cloned()),
+                None => odb.try_read(id),
+            },
+        }
+    }
+
+    // Apply a delta if its base is available; returns false when it is not yet
+    fn try_resolve(&mut self, odb: &dyn ObjectDatabase, pending: &PendingDelta) -> Result<bool> {
+        let Some((base_type, base_content)) = self.find_base(odb, &pending.base)? else {
+            return Ok(false);
+        };
+
+        let target_content = apply_delta(&base_content, &pending.delta)?;
+        self.insert(pending.offset, &base_type, target_content);
+        Ok(true)
+    }
+}
+
+// Parse the negative base offset of an ofs_delta entry
+fn parse_ofs_delta_offset(data: &[u8]) -> Result<(usize, &[u8])> {
+    let truncated = || corrupt_pack("Truncated ofs_delta base offset");
+
+    let mut i = 0;
+    let mut byte = *data.first().ok_or_else(truncated)?;
+    let mut offset = (byte & 0b0111_1111) as usize;
+
+    // Each continuation byte adds one before shifting, so encodings are unique
+    while byte & 0b1000_0000 != 0 {
+        i += 1;
+        byte = *data.get(i).ok_or_else(truncated)?;
+        offset = offset
+            .checked_add(1)
+            .filter(|offset| offset.leading_zeros() >= 7)
+            .map(|offset| (offset << 7) | (byte & 0b0111_1111) as usize)
+            .ok_or_else(|| corrupt_pack("ofs_delta base offset overflows"))?;
+    }
+
+    // A delta cannot be its own base
+    if offset == 0 {
+        return Err(corrupt_pack("ofs_delta base offset is zero"));
+    }
+
+    Ok((offset, &data[i + 1..]))
+}
+
+// Resolve queued deltas until every base is available or no progress is made
+fn resolve_pending_deltas(
+    odb: &dyn ObjectDatabase,
+    objects: &mut PackObjects,
+    mut pending: Vec<PendingDelta>,
+) -> Result<()> {
+    while !pending.is_empty() {
+        let queued = pending.len();
+        let mut unresolved = Vec::new();
+
+        for delta in pending {
+            if !objects.try_resolve(odb, &delta)? {
+                unresolved.push(delta);
+            }
+        }
+
+        if unresolved.len() == queued {
+            let missing: Vec<String> = unresolved
+                .iter()
+                .map(|delta| match &delta.base {
+                    DeltaBase::Ref(id) => id.to_hex(),
+                    DeltaBase::Ofs(offset) => format!("<pack offset {}>", offset),
+                })
+                .collect();
+            return Err(corrupt_pack(format!(
+                "Could not resolve {} delta object(s); missing bases: {}",
+                unresolved.len(),
+                missing.join(", ")
+            )));
+        }
+
+        pending = unresolved;
+    }
+
+    Ok(())
+}
+
+// Parse every pack entry, resolving deltas; returns the objects and the entries' end
+fn parse_packfile(odb: &dyn ObjectDatabase, pack_file_data: &[u8]) -> Result<(PackObjects, usize)> {
+    if pack_file_data.len() < 12 {
+        return Err(corrupt_pack("Packfile too short"));
+    }
+
+    // Check header
+    if &pack_file_data[0..4] != b"PACK" {
+        return Err(corrupt_pack("Invalid packfile header"));
+    }
+
+    // Check version
+    let version = u32::from_be_bytes([
+        pack_file_data[4],
+        pack_file_data[5],
+        pack_file_data[6],
+        pack_file_data[7],
+    ]);
+    if version != 2 {
+        return Err(corrupt_pack(format!(
+            "Unsupported packfile version: {}",
+            version
+        )));
+    }
+
+    // Get number of objects
+    let num_objects = u32::from_be_bytes([
+        pack_file_data[8],
+        pack_file_data[9],
+        pack_file_data[10],
+        pack_file_data[11],
+    ]);
+
+    let mut data = &pack_file_data[12..];
+    let mut objects = PackObjects::default();
+    let mut pending = Vec::new();
+
+    for _ in 0..num_objects {
+        let entry_offset = pack_file_data.len() - data.len();
+        let (obj_type, size, header_len) = parse_entry_header(data)?;
+        data = &data[header_len..];
+
+        match obj_type {
+            1..=4 => {
+                // Regular objects (commit, tree, blob, tag)
+                let (mut content, consumed) = inflate_entry(data)?;
+                data = &data[consumed..];
+
+                content.truncate(size as usize);
+                let type_name = object_type_name(obj_type).unwrap_or("unknown");
+                objects.insert(entry_offset, type_name, content);
+            }
+            7 => {
+                // ref_delta
+                if data.len() < 20 {
+                    return Err(corrupt_pack("Not enough data for ref delta"));
+                }
+
+                let base_id = ObjectId::from_bytes(&data[..20])?;
+                data = &data[20..];
+
+                let (delta_content, consumed) = inflate_entry(data)?;
+                data = &data[consumed..];
+
+                let delta = PendingDelta {
+                    offset: entry_offset,
+                    base: DeltaBase::Ref(base_id),
+                    delta: delta_content,
+                };
+                if !objects.try_resolve(odb, &delta)? {
+                    pending.push(delta);
+                }
+            }
+            6 => {
+                // ofs_delta
+                let (base_distance, remaining) = parse_ofs_delta_offset(data)?;
+                data = remaining;
+
+                let (delta_content, consumed) = inflate_entry(data)?;
+                data = &data[consumed..];
+
+                let base_offset = entry_offset.checked_sub(base_distance).ok_or_else(|| {
+                    corrupt_pack("ofs_delta base offset points before the pack start")
+                })?;
+                let delta = PendingDelta {
+                    offset: entry_offset,
+                    base: DeltaBase::Ofs(base_offset),
+                    delta: delta_content,
+                };
+                // The base precedes the delta but may itself be a deferred delta
+                if !objects.try_resolve(odb, &delta)? {
+                    pending.push(delta);
+                }
+            }
+            _ => {
+                return Err(corrupt_pack(format!("Unknown object type: {}", obj_type)));
+            }
+        }
+
+        let entry_end = pack_file_data.len() - data.len();
+        let crc32 = crc32fast::hash(&pack_file_data[entry_offset..entry_end]);
+        objects.crc32_by_offset.insert(entry_offset, crc32);
+    }
+
+    resolve_pending_deltas(odb, &mut objects, pending)?;
+
+    Ok((objects, pack_file_data.len() - data.len()))
+}
+
+// Build a version 2 pack index with the same layout as git index-pack
+fn build_pack_index(objects: &PackObjects, pack_checksum: &[u8]) -> Result<Vec<u8>> {
+    let mut entries = Vec::with_capacity(objects.offsets_by_id.len());
+    for (id, &offset) in &objects.offsets_by_id {
+        let crc32 = objects.crc32_by_offset.get(&offset).copied().unwrap_or(0);
+        entries.push((*id, crc32, offset as u64));
+    }
+    entries.sort_by_key(|entry| entry.0);
+
+    let mut index = Vec::new();
+    index.extend_from_slice(b"\xfftOc");
+    index.extend_from_slice(&2u32.to_be_bytes());
+
+    // Fanout table: number of objects whose first byte is <= each value
+    let mut fanout = [0u32; 256];
+    for (id, _, _) in &entries {
+        fanout[id.as_bytes()[0] as usize] += 1;
+    }
+    let mut total = 0;
+    for count in fanout {
+        total += count;
+        index.extend_from_slice(&total.to_be_bytes());
+    }
+
+    for (id, _, _) in &entries {
+        index.extend_from_slice(id.as_bytes());
+    }
+    for (_, crc32, _) in &entries {
+        index.extend_from_slice(&crc32.to_be_bytes());
+    }
+
+    // Small offsets inline; large ones point into the 64-bit table
+    let mut large_offsets = Vec::new();
+    for (_, _, offset) in &entries {
+        if *offset < LARGE_OFFSET {
+            index.extend_from_slice(&(*offset as u32).to_be_bytes());
+        } else {
+            let slot = large_offsets.len() as u32 | LARGE_OFFSET as u32;
+            index.extend_from_slice(&slot.to_be_bytes());
+            large_offsets.push(*offset);
+        }
+    }
+    for offset in large_offsets {
+        index.extend_from_slice(&offset.to_be_bytes());
+    }
+
+    index.extend_from_slice(pack_checksum);
+    let index_checksum = Sha1::digest(&index);
+    index.extend_from_slice(&index_checksum);
+
+    Ok(index)
+}
+
+// Store a received packfile in a pack directory together with its index
+pub fn index_pack(odb: &dyn ObjectDatabase, pack_dir: &Path, pack_file_data: &[u8]) -> Result<()> {
+    let (objects, entries_end) = parse_packfile(odb, pack_file_data)?;
+
+    // Verify the trailing checksum, which also names the pack
+    let pack_checksum = pack_file_data
+        .get(entries_end..entries_end + 20)
+        .ok_or_else(|| corrupt_pack("Packfile is missing its trailing checksum"))?;
+    if Sha1::digest(&pack_file_data[..entries_end]).as_slice() != pack_checksum {
+        return Err(corrupt_pack("Packfile checksum mismatch"));
+    }
+
+    let index = build_pack_index(&objects, pack_checksum)?;
+
+    fs::create_dir_all(pack_dir)?;
+    let pack_name = format!("pack-{}", hex::encode(pack_checksum));
+    fs::write(
+        pack_dir.join(format!("{}.pack", pack_name)),
+        &pack_file_data[..entries_end + 20],
+    )?;
+    fs::write(pack_dir.join(format!("{}.idx", pack_name)), index)?;
+
+    Ok(())
+}
+
+// A version 2 pack index loaded into memory, with the pack it describes
+pub struct PackIndex {
+    idx_path: PathBuf,
+    pack_path: PathBuf,
+    data: Vec<u8>,
+    total: usize,
+}
+
+impl PackIndex {
+    // Load an index from disk, checking its version and that its tables fit
+    pub fn open(idx_path: &Path) -> Result<Self> {
+        Self::parse(idx_path, fs::read(idx_path)?)
+    }
+
+    // Check an index held in memory; `idx_path` names it and its pack
+    fn parse(idx_path: &Path, data: Vec<u8>) -> Result<Self> {
+        if data.len() < INDEX_HEADER_LEN as usize
+            || &data[0..4] != b"\xfftOc"
+            || data[4..8] != 2u32.to_be_bytes()
+        {
+            return Err(corrupt_pack(format!(
+                "Unsupported pack index: {}",
+                idx_path.display()
+            )));
+        }
+
+        let mut index = Self {
+            idx_path: idx_path.to_path_buf(),
+            pack_path: idx_path.with_extension("pack"),
+            data,
+            total: 0,
+        };
+
+        // The fanout table counts names up to each first byte, so it never shrinks
+        let mut previous = 0;
+        for first in 0..256 {
+            let count = index.u32_at(8 + first * 4);
+            if count < previous {
+                return Err(corrupt_pack(format!(
+                    "Pack index fanout is not monotonic: {}",
+                    idx_path.display()
+                )));
+            }
+            previous = count;
+        }
+        index.total = previous as usize;
+
+        // Names, CRCs and offsets, then both trailing checksums
+        let tables_len = index.total * (ObjectId::LEN + 4 + 4) + 2 * ObjectId::LEN;
+        if index.data.len() < INDEX_HEADER_LEN as usize + tables_len {
+            return Err(corrupt_pack(format!(
+                "Pack index is truncated: {}",
+                idx_path.display()
+            )));
+        }
+        Ok(index)
+    }
+
+    // Read a big-endian u32 at `pos`, which the caller has bounds-checked
+    fn u32_at(&self, pos: usize) -> u32 {
+        u32::from_be_bytes([
+            self.data[pos],
+            self.data[pos + 1],
+            self.data[pos + 2],
+            self.data[pos + 3],
+        ])
+    }
+
+    // Number of names sharing a first byte of at most `first`
+    fn fanout(&self, first: usize) -> usize {
+        self.u32_at(8 + first * 4) as usize
+    }
+
+    // The name at position `i` of the sorted name table
+    fn name(&self, i: usize) -> &[u8] {
+        let start = INDEX_HEADER_LEN as usize + i * ObjectId::LEN;
+        &self.data[start..start + ObjectId::LEN]
+    }
+
+    // Every name in the index, in sorted order
+    pub fn ids(&self) -> Result<Vec<ObjectId>> {
+        (0..self.total)
+            .map(|i| ObjectId::from_bytes(self.name(i)))
+            .collect()
+    }
+}
+
+// Find an object's pack offset by binary searching a loaded version 2 index
+fn find_in_index(index: &PackIndex, id: &ObjectId) -> Result<Option<u64>> {
+    // The fanout table bounds the range of names sharing the first byte
+    let first = id.as_bytes()[0] as usize;
+    let mut lo = match first {
+        0 => 0,
+        _ => index.fanout(first - 1),
+    };
+    let mut hi = index.fanout(first);
+
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        match index.name(mid).cmp(id.as_bytes()) {
+            std::cmp::Ordering::Less => lo = mid + 1,
+            std::cmp::Ordering::Greater => hi = mid,
+            std::cmp::Ordering::Equal => {
+                // Skip the name and CRC tables to reach the offset tables
+                let offsets_pos = INDEX_HEADER_LEN as usize + index.total * 24;
+                let offset = index.u32_at(offsets_pos + mid * 4) as u64;
+                if offset & LARGE_OFFSET == 0 {
+                    return Ok(Some(offset));
+                }
+
+                let slot = (offset & !LARGE_OFFSET) as usize;
+                let large_pos = offsets_pos + index.total * 4 + slot * 8;
+                // The table ends where the trailing checksums begin
+                let tables_end = index.data.len() - 2 * ObjectId::LEN;
+                let large = index.data[..tables_end]
+                    .get(large_pos..large_pos + 8)
+                    .ok_or_else(|| {
+                        corrupt_pack(format!(
+                            "Pack index large offset is out of range: {}",
+                            index.idx_path.display()
+                        ))
+                    })?;
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(large);
+                return Ok(Some(u64::from_be_bytes(bytes)));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+// Inflate the zlib stream starting at `pos` in a pack
+fn inflate_at(pack: &mut File, pos: u64) -> Result<Vec<u8>> {
+    pack.seek(SeekFrom::Start(pos))?;
+    let mut decoder = ZlibDecoder::new(BufReader::new(pack));
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+// Base of a delta entry stored in a pack, and where its delta data starts
+enum StoredDeltaBase {
+    Offset(u64),
+    Name(ObjectId),
+}
+
+// Open a pack at an entry and parse its header and delta base, if any
+fn open_pack_entry(
+    pack_path: &Path,
+    offset: u64,
+) -> Result<(File, u8, u64, Option<StoredDeltaBase>, u64)> {
+    let mut pack = File::open(pack_path)?;
+    pack.seek(SeekFrom::Start(offset))?;
+
+    // Longest prefix: a 10-byte size header followed by a 20-byte base name
+    let mut header = Vec::new();
+    (&mut pack).take(32).read_to_end(&mut header)?;
+    let (obj_type, size, header_len) = parse_entry_header(&header)?;
+
+    let (base, data_start) = match obj_type {
+        6 => {
+            let (base_distance, remaining) = parse_ofs_delta_offset(&header[header_len..])?;
+            let base_offset = offset.checked_sub(base_distance as u64).ok_or_else(|| {
+                corrupt_pack("ofs_delta base offset points before the pack start")
+            })?;
+            let data_start = offset + (header.len() - remaining.len()) as u64;
+            (Some(StoredDeltaBase::Offset(base_offset)), data_start)
+        }
+        7 => {
+            let base_sha = header
+                .get(header_len..header_len + 20)
+                .ok_or_else(|| corrupt_pack("Not enough data for ref delta"))?;
+            let data_start = offset + header_len as u64 + 20;
+            (
+                Some(StoredDeltaBase::Name(ObjectId::from_bytes(base_sha)?)),
+                data_start,
+            )
+        }
+        _ => (None, offset + header_len as u64),
+    };
+
+    Ok((pack, obj_type, size, base, data_start))
+}
+
+// Helper function to find the pack and offset holding a delta's base, refusing
+// to follow a chain any deeper than git would write one
+fn locate_delta_base(
+    packs: &[PackIndex],
+    pack_path: &Path,
+    base: StoredDeltaBase,
+    depth: usize,
+) -> Result<(PathBuf, u64)> {
+    if depth >= MAX_DELTA_DEPTH {
+        return Err(corrupt_pack(format!(
+            "Delta chain in {} is deeper than {}",
+            pack_path.display(),
+            MAX_DELTA_DEPTH
+        )));
+    }
+
+    match base {
+        StoredDeltaBase::Offset(base_offset) => Ok((pack_path.to_path_buf(), base_offset)),
+        StoredDeltaBase::Name(base_id) => find_packed(packs, &base_id)?
+            .ok_or_else(|| corrupt_pack(format!("Missing delta base {}", base_id))),
+    }
+}
+
+// Read and inflate the pack entry at `offset`, resolving delta chains; `depth`
+// counts the deltas already followed to get here
+fn read_pack_entry(
+    packs: &[PackIndex],
+    pack_path: &Path,
+    offset: u64,
+    depth: usize,
+) -> Result<(String, Vec<u8>)> {
+    let (mut pack, obj_type, size, base, data_start) = open_pack_entry(pack_path, offset)?;
+
+    let Some(base) = base else {
+        let type_name = object_type_name(obj_type)
+            .ok_or_else(|| corrupt_pack(format!("Unknown object type: {}", obj_type)))?;
+        let mut content = inflate_at(&mut pack, data_start)?;
+        content.truncate(size as usize);
+        return Ok((type_name.to_string(), content));
+    };
+
+    let delta = inflate_at(&mut pack, data_start)?;
+    let (base_pack, base_offset) = locate_delta_base(packs, pack_path, base, depth)?;
+    let (base_type, base_content) = read_pack_entry(packs, &base_pack, base_offset, depth + 1)?;
+    Ok((base_type, apply_delta(&base_content, &delta)?))
+}
+
+// Read the type and size of the pack entry at `offset` without applying deltas
+fn read_pack_entry_header(
+    packs: &[PackIndex],
+    pack_path: &Path,
+    offset: u64,
+    depth: usize,
+) -> Result<(String, usize)> {
+    let (mut pack, obj_type, size, base, data_start) = open_pack_entry(pack_path, offset)?;
+
+    let Some(base) = base else {
+        let type_name = object_type_name(obj_type)
+            .ok_or_else(|| corrupt_pack(format!("Unknown object type: {}", obj_type)))?;
+        return Ok((type_name.to_string(), size as usize));
+    };
+
+    // The delta records the result size; the type comes from the end of the chain
+    let delta = inflate_at(&mut pack, data_start)?;
+    let (_, remaining) = parse_size(&delta)?;
+    let (target_size, _) = parse_size(remaining)?;
+    let (base_pack, base_offset) = locate_delta_base(packs, pack_path, base, depth)?;
+    let (base_type, _) = read_pack_entry_header(packs, &base_pack, base_offset, depth + 1)?;
+    Ok((base_type, target_size))
+}
+
+// Find the pack containing an object and the object's offset within it
+fn find_packed(packs: &[PackIndex], id: &ObjectId) -> Result<Option<(PathBuf, u64)>> {
+    for index in packs {
+        if let Some(offset) = find_in_index(index, id)? {
+            return Ok(Some((index.pack_path.clone(), offset)));
+        }
+    }
+
+    Ok(None)
+}
+
+// Load the indexes under a pack directory that are not loaded yet, as after a
+// clone or fetch adds a pack; returns whether any were found
+pub fn load_new_indexes(pack_dir: &Path, packs: &mut Vec<PackIndex>) -> Result<bool> {
+    let entries = match fs::read_dir(pack_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut found = false;
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("idx")
+            || packs.iter().any(|index| index.idx_path == path)
+        {
+            continue;
+        }
+        packs.push(PackIndex::open(&path)?);
+        found = true;
+    }
+    Ok(found)
+}
+
+// List the names of every object in the loaded packs
+pub fn list_packed_objects(packs: &[PackIndex]) -> Result<Vec<ObjectId>> {
+    let mut ids = Vec::new();
+    for index in packs {
+        ids.extend(index.ids()?);
+    }
+    Ok(ids)
+}
+
+// Look up an object in the loaded index/pack pairs
+pub fn read_packed_object(packs: &[PackIndex], id: &ObjectId) -> Result<Option<(String, Vec<u8>)>> {
+    match find_packed(packs, id)? {
+        Some((pack_path, offset)) => read_pack_entry(packs, &pack_path, offset, 0).map(Some),
+        None => Ok(None),
+    }
+}
+
+// Look up an object's type and size in the loaded index/pack pairs
+pub fn read_packed_header(packs: &[PackIndex], id: &ObjectId) -> Result<Option<(String, usize)>> {
+    match find_packed(packs, id)? {
+        Some((pack_path, offset)) => read_pack_entry_header(packs, &pack_path, offset, 0).map(Some),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::odb::InMemoryObjectDatabase;
+
+    // A pack written by git pack-objects holding one commit, its tree and the
+    // blobs "hello\n" and "hello\nworld\n", all stored whole
+    const FIXTURE_PACK: &str = concat!(
+        "5041434b00000002000000049707789c2b294a4d55484b343037324eb13437b14c324db1",
+        "34304934b34c4c34b3304b4d354e4e3330354c364d4a4a3535e24a2c2dc9c82f524854b0",
+        "4974a8b0533034378002056d10c9959c9f9b9b5952928a470957665e66091700d37f1fd2",
+        "aa03789c33343030333151486438c768a6caccbde2f64ab6ef61d3eae7cd593cc5cdcb10",
+        "2299c43065aad7de25735f2446b1f4f7eaa56cbdd7d2a7be100047d6166236789ccb48cd",
+        "c9c9e70200084b021f3c789ccb48cdc9c9e72acf2fca49e102001dd804514463f4238743",
+        "9905e3f484d4e51943a3127b5584",
+    );
+
+    // Helper function to encode a delta size the way git does, low bits first
+    fn encode_size(mut size: usize, out: &mut Vec<u8>) {
+        while size >= 0x80 {
+            out.push((size & 0x7f) as u8 | 0x80);
+            size >>= 7;
+        }
+        out.push(size as u8);
+    }
+
+    // Helper function to build a delta from its sizes and instructions
+    fn delta(base_size: usize, target_size: usize, instructions: &[u8]) -> Vec<u8> {
+        let mut delta = Vec::new();
+        encode_size(base_size, &mut delta);
+        encode_size(target_size, &mut delta);
+        delta.extend_from_slice(instructions);
+        delta
+    }
+
+    // Helper function to check for a CorruptPack error rather than a panic
+    fn is_corrupt<T>(result: Result<T>) -> bool {
+        matches!(result, Err(Error::CorruptPack(_)))
+    }
+
+    #[test]
+    fn applies_copy_and_insert_instructions() {
+        let base = b"hello world";
+        // Copy 5 bytes from offset 6, insert ", hi", then copy "hello" from 0
+        let instructions = [0b1001_0001, 6, 5, 4, b',', b' ', b'h', b'i', 0b1001_0000, 5];
+        let result = apply_delta(base, &delta(11, 14, &instructions)).unwrap();
+        assert_eq!(result, b"world, hihello");
+    }
+
+    #[test]
+    fn reads_multi_byte_copy_offsets_and_sizes() {
+        let base: Vec<u8> = (0..0x20000u32).map(|i| (i % 251) as u8).collect();
+        // Offset 0x10003 from bytes 0 and 2, size 0x100 from byte 1 alone
+        let instructions = [0b1010_0101, 0x03, 0x01, 0x01];
+        let result = apply_delta(&base, &delta(base.len(), 0x100, &instructions)).unwrap();
+        assert_eq!(result, &base[0x10003..0x10103]);
+    }
+
+    #[test]
+    fn treats_copy_size_zero_as_0x10000() {
+        let base: Vec<u8> = (0..0x10010u32).map(|i| (i % 251) as u8).collect();
+        // No size bytes at all, and one offset byte of 0x10
+        let result = apply_delta(&base, &delta(base.len(), 0x10000, &[0b1000_0001, 0x10])).unwrap();
+        assert_eq!(result, &base[0x10..]);
+    }
+
+    #[test]
+    fn rejects_truncated_and_inconsistent_deltas() {
+        let base = b"hello world";
+        // Insert of 5 bytes with only 2 present
+        assert!(is_corrupt(apply_delta(
+            base,
+            &delta(11, 5, &[5, b'a', b'b'])
+        )));
+        // Copy whose offset byte is missing
+        assert!(is_corrupt(apply_delta(base, &delta(11, 5, &[0b1001_0001]))));
+        // Size header cut off in its continuation byte
+        assert!(is_corrupt(apply_delta(base, &[0x8b])));
+        // Copy reaching past the end of the base
+        assert!(is_corrupt(apply_delta(
+            base,
+            &delta(11, 5, &[0b1001_0001, 8, 5])
+        )));
+        // Reserved opcode
+        assert!(is_corrupt(apply_delta(base, &delta(11, 0, &[0]))));
+        // Base of the wrong size
+        assert!(is_corrupt(apply_delta(base, &delta(12, 1, &[1, b'a']))));
+        // Result shorter or longer than declared
+        assert!(is_corrupt(apply_delta(base, &delta(11, 3, &[1, b'a']))));
+        assert!(is_corrupt(apply_delta(
+            base,
+            &delta(11, 1, &[2, b'a', b'b'])
+        )));
+    }
+
+    #[test]
+    fn rejects_oversized_deltas() {
+        let base = b"hello world";
+        // A declared result far beyond what memory could hold
+        assert!(is_corrupt(apply_delta(
+            base,
+            &delta(11, usize::MAX, &[1, b'a'])
+        )));
+        // A size with more bits than fit in usize
+        let mut overflowing = vec![0xff; 10];
+        overflowing.push(0x7f);
+        assert!(is_corrupt(apply_delta(base, &overflowing)));
+    }
+
+    #[test]
+    fn decodes_ofs_delta_offsets_with_the_continuation_bias() {
+        // Every continuation byte adds one before shifting
+        for (encoded, expected) in [
+            (&[0x01][..], 1),
+            (&[0x7f][..], 127),
+            (&[0x80, 0x00][..], 128),
+            (&[0x80, 0x7f][..], 255),
+            (&[0x81, 0x00][..], 256),
+            (&[0xff, 0x7f][..], 16511),
+            (&[0x80, 0x80, 0x00][..], 16512),
+        ] {
+            let mut data = encoded.to_vec();
+            data.push(0xaa);
+            let (offset, rest) = parse_ofs_delta_offset(&data).unwrap();
+            assert_eq!(offset, expected, "encoding {:02x?}", encoded);
+            assert_eq!(rest, &[0xaa]);
+        }
+
+        assert!(is_corrupt(parse_ofs_delta_offset(&[0x00])));
+        assert!(is_corrupt(parse_ofs_delta_offset(&[0x80])));
+        assert!(is_corrupt(parse_ofs_delta_offset(&[0xff; 12])));
+    }
+
+    #[test]
+    fn resolves_deltas_of_deltas_in_any_order() {
+        let odb = InMemoryObjectDatabase::new();
+        let outside = odb.write("blob", b"outside").unwrap();
+        let mut objects = PackObjects::default();
+        objects.insert(12, "blob", b"hello world".to_vec());
+
+        // 40 builds on 12, 60 builds on 40, and 80 on a base outside the pack;
+        // queued deepest first so the chain takes several rounds
+        let pending = vec![
+            PendingDelta {
+                offset: 60,
+                base: DeltaBase::Ofs(40),
+                delta: delta(14, 5, &[0b1001_0001, 9, 5]),
+            },
+            PendingDelta {
+                offset: 40,
+                base: DeltaBase::Ofs(12),
+                delta: delta(11, 14, &[0b1001_0000, 11, 3, b'!', b'!', b'!']),
+            },
+            PendingDelta {
+                offset: 80,
+                base: DeltaBase::Ref(outside),
+                delta: delta(7, 3, &[0b1001_0000, 3]),
+            },
+        ];
+        resolve_pending_deltas(&odb, &mut objects, pending).unwrap();
+
+        let content = |offset| objects.by_offset[&offset].1.clone();
+        assert_eq!(content(40), b"hello world!!!");
+        assert_eq!(content(60), b"ld!!!");
+        assert_eq!(content(80), b"out");
+        let id = ObjectId::hash_object("blob", b"ld!!!");
+        assert_eq!(objects.offsets_by_id[&id], 60);
+    }
+
+    #[test]
+    fn reports_deltas_whose_base_never_appears() {
+        let odb = InMemoryObjectDatabase::new();
+        let mut objects = PackObjects::default();
+        let pending = vec![PendingDelta {
+            offset: 40,
+            base: DeltaBase::Ofs(12),
+            delta: delta(1, 1, &[1, b'a']),
+        }];
+        assert!(is_corrupt(resolve_pending_deltas(
+            &odb,
+            &mut objects,
+            pending
+        )));
+    }
+
+    #[test]
+    fn builds_the_index_git_builds_and_reads_it_back() {
+        let pack = hex::decode(FIXTURE_PACK).unwrap();
+        let (objects, entries_end) = parse_packfile(&InMemoryObjectDatabase::new(), &pack).unwrap();
+        let checksum = &pack[entries_end..];
+        assert_eq!(
+            hex::encode(checksum),
+            "4463f42387439905e3f484d4e51943a3127b5584"
+        );
+
+        // git index-pack writes an index whose own checksum is this
+        let data = build_pack_index(&objects, checksum).unwrap();
+        assert_eq!(
+            hex::encode(&data[data.len() - 20..]),
+            "eb2867a438b6aa0805df908cad036c2718008e10"
+        );
+        let index = PackIndex::parse(Path::new("fixture.idx"), data).unwrap();
+
+        // Names start with 0x50, 0x94, 0xce and 0xfa
+        assert_eq!(index.total, 4);
+        for (first, count) in [
+            (0x00, 0),
+            (0x4f, 0),
+            (0x50, 1),
+            (0x94, 2),
+            (0xcd, 2),
+            (0xce, 3),
+        ] {
+            assert_eq!(index.fanout(first), count, "fanout[{:#04x}]", first);
+        }
+        assert_eq!(index.fanout(0xff), 4);
+
+        // Offsets as listed by git verify-pack, CRCs as listed by git show-index
+        let crc_table = INDEX_HEADER_LEN as usize + index.total * ObjectId::LEN;
+        for (i, (name, offset, crc32)) in [
+            ("50ec3bd9df8ec3e07316c5f409a44d4f61dd7627", 12, 0x315f96f3),
+            ("94954abda49de8615a048f8d2e64b5de848e27a1", 189, 0x21ba35fa),
+            ("ce013625030ba8dba906f756967f9e9ca394464a", 174, 0x52941500),
+            ("fa0723d9749b5d904a69aa686ee3cf051c5bbe52", 108, 0x31f7b6ba),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let id = ObjectId::from_hex(name).unwrap();
+            assert_eq!(index.name(i), id.as_bytes());
+            assert_eq!(
+                find_in_index(&index, &id).unwrap(),
+                Some(offset),
+                "{}",
+                name
+            );
+            assert_eq!(index.u32_at(crc_table + i * 4), crc32, "{}", name);
+        }
+
+        let absent = ObjectId::hash_object("blob", b"absent");
+        assert_eq!(find_in_index(&index, &absent).unwrap(), None);
+    }
+
+    #[test]
+    fn keeps_offsets_from_2_gib_in_the_64_bit_table() {
+        let mut objects = PackObjects::default();
+        let offsets = [
+            12,
+            LARGE_OFFSET as usize - 1,
+            LARGE_OFFSET as usize,
+            0x1_2345_6789,
+        ];
+        let mut ids = Vec::new();
+        for (seed, &offset) in offsets.iter().enumerate() {
+            let content = vec![seed as u8];
+            ids.push(ObjectId::hash_object("blob", &content));
+            objects.insert(offset, "blob", content);
+            objects.crc32_by_offset.insert(offset, seed as u32);
+        }
+
+        let data = build_pack_index(&objects, &[0; 20]).unwrap();
+        // Two offsets need the 64-bit table: 8 bytes each after the 4-byte ones
+        assert_eq!(data.len(), INDEX_HEADER_LEN as usize + 4 * 28 + 2 * 8 + 40);
+        let index = PackIndex::parse(Path::new("large.idx"), data).unwrap();
+        for (id, &offset) in ids.iter().zip(&offsets) {
+            assert_eq!(find_in_index(&index, id).unwrap(), Some(offset as u64));
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_indexes() {
+        let mut objects = PackObjects::default();
+        objects.insert(0x1_0000_0000, "blob", b"far".to_vec());
+        let data = build_pack_index(&objects, &[0; 20]).unwrap();
+
+        let mut version_1 = data.clone();
+        version_1[7] = 1;
+        assert!(is_corrupt(PackIndex::parse(Path::new("v1.idx"), version_1)));
+
+        let truncated = data[..data.len() - 41].to_vec();
+        assert!(is_corrupt(PackIndex::parse(
+            Path::new("short.idx"),
+            truncated
+        )));
+
+        let mut shrinking = data.clone();
+        shrinking[8 + 4 * 255..8 + 4 * 256].copy_from_slice(&0u32.to_be_bytes());
+        assert!(is_corrupt(PackIndex::parse(
+            Path::new("fanout.idx"),
+            shrinking
+        )));
+
+        // A large offset slot pointing past the 64-bit table
+        let mut dangling = data;
+        let slot = INDEX_HEADER_LEN as usize + 24;
+        dangling[slot..slot + 4].copy_from_slice(&(LARGE_OFFSET as u32 | 1).to_be_bytes());
+        let index = PackIndex::parse(Path::new("dangling.idx"), dangling).unwrap();
+        let id = ObjectId::hash_object("blob", b"far");
+        assert!(is_corrupt(find_in_index(&index, &id)));
+    }
+}
@@ -0,0 +1,316 @@
+use crate::error::{Error, Result};
+use crate::ident::reflog_signature;
+use crate::object::{ObjectId, Tag};
+use crate::repository::{LockFile, Repository};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+
+// How many symbolic refs may be chained before we assume a loop, as in git
+const MAX_SYMREF_DEPTH: usize = 5;
+
+// Where a short name such as "main" is looked for, in git's order
+const DWIM_RULES: [&str; 6] = [
+    "%s",
+    "refs/%s",
+    "refs/tags/%s",
+    "refs/heads/%s",
+    "refs/remotes/%s",
+    "refs/remotes/%s/HEAD",
+];
+
+// What a ref holds: an object name, or the name of another ref
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RefTarget {
+    Direct(ObjectId),
+    Symbolic(String),
+}
+
+// Check a ref name against git's rules: no empty, dot-led or ".lock" components,
+// no "..", "@{", control characters or any of ` ~^:?*[\`
+pub fn is_valid_ref_name(name: &str) -> bool {
+    name != "@"
+        && !name.ends_with('.')
+        && !name.contains("..")
+        && !name.contains("@{")
+        && !name
+            .bytes()
+            .any(|b| b < 0x20 || b == 0x7f || b" ~^:?*[\\".contains(&b))
+        && name
+            .split('/')
+            .all(|part| !part.is_empty() && !part.starts_with('.') && !part.ends_with(".lock"))
+}
+
+// Helper function to check for a ref that lives directly in the git directory:
+// HEAD or another all-caps name like ORIG_HEAD
+fn is_pseudo_ref_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_uppercase() || b == b'_')
+}
+
+// Helper function to check that a ref can be written: it lives under refs/, or
+// is a pseudo-ref
+fn check_writable_ref_name(name: &str) -> Result<()> {
+    match is_valid_ref_name(name) && (is_pseudo_ref_name(name) || name.starts_with("refs/")) {
+        true => Ok(()),
+        false => Err(Error::InvalidRef(name.to_string())),
+    }
+}
+
+// The refs recorded in a packed-refs file, with the object each annotated tag
+// peels to when the file records it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PackedRefs {
+    pub refs: BTreeMap<String, (ObjectId, Option<ObjectId>)>,
+}
+
+impl PackedRefs {
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut refs: BTreeMap<String, (ObjectId, Option<ObjectId>)> = BTreeMap::new();
+        let mut last_ref: Option<&str> = None;
+        let unexpected = |line: &str| Error::CorruptPackedRefs(line.to_string());
+
+        for line in content.lines() {
+            // The header lists the file's traits, such as "peeled"
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+
+            // A "^<id>" line peels the tag ref just before it
+            if let Some(hex) = line.strip_prefix('^') {
+                let peeled = ObjectId::from_hex(hex).map_err(|_| unexpected(line))?;
+                let entry = last_ref
+                    .and_then(|name| refs.get_mut(name))
+                    .ok_or_else(|| unexpected(line))?;
+                entry.1 = Some(peeled);
+                continue;
+            }
+
+            let (hex, name) = line.split_once(' ').ok_or_else(|| unexpected(line))?;
+            let id = ObjectId::from_hex(hex).map_err(|_| unexpected(line))?;
+            if !is_valid_ref_name(name) {
+                return Err(unexpected(line));
+            }
+            refs.insert(name.to_string(), (id, None));
+            last_ref = Some(name);
+        }
+
+        Ok(Self { refs })
+    }
+}
+
+impl Repository {
+    // Read packed-refs; a repository without one has no packed refs
+    pub fn packed_refs(&self) -> Result<PackedRefs> {
+        match fs::read_to_string(self.git_dir().join("packed-refs")) {
+            Ok(content) => PackedRefs::parse(&content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(PackedRefs::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Read what a single ref holds without following it: the loose file if there
+    // is one, otherwise the packed-refs entry
+    pub fn read_ref_target(&self, name: &str) -> Result<Option<RefTarget>> {
+        if !is_valid_ref_name(name) {
+            return Ok(None);
+        }
+
+        let path = self.git_dir().join(name);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            // A directory such as refs/heads is not a ref, and cannot be packed either
+            Err(_) if path.is_dir() => return Ok(None),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let packed = self.packed_refs()?;
+                return Ok(packed.refs.get(name).map(|(id, _)| RefTarget::Direct(*id)));
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let content = content.trim_end();
+        match content.strip_prefix("ref: ") {
+            Some(target) => Ok(Some(RefTarget::Symbolic(target.trim().to_string()))),
+            None => ObjectId::from_hex(content)
+                .map(|id| Some(RefTarget::Direct(id)))
+                .map_err(|_| Error::InvalidRef(name.to_string())),
+        }
+    }
+
+    // Follow symbolic refs from `name` to the ref that holds an object name,
+    // returning that ref's name and value; the value is None for a ref that does
+    // not exist yet, such as the branch of a repository without commits
+    pub fn resolve_ref(&self, name: &str) -> Result<(String, Option<ObjectId>)> {
+        let mut name = name.to_string();
+        for _ in 0..MAX_SYMREF_DEPTH {
+            match self.read_ref_target(&name)? {
+                Some(RefTarget::Symbolic(target)) => name = target,
+                Some(RefTarget::Direct(id)) => return Ok((name, Some(id))),
+                None => return Ok((name, None)),
+            }
+        }
+        Err(Error::InvalidRef(name))
+    }
+
+    // Read a ref such as HEAD or refs/heads/main, following symbolic refs
+    pub fn read_ref(&self, name: &str) -> Result<Option<ObjectId>> {
+        Ok(self.resolve_ref(name)?.1)
+    }
+
+    // Expand a short name like "main" or "origin" to the first full ref name in
+    // git's search order that exists
+    pub fn dwim_ref(&self, name: &str) -> Result<Option<String>> {
+        for rule in DWIM_RULES {
+            // Only pseudo-refs are looked up as they are, so files like config
+            // or index never shadow a branch of the same name
+            if rule == "%s" && !is_pseudo_ref_name(name) {
+                continue;
+            }
+            let candidate = rule.replace("%s", name);
+            match self.read_ref(&candidate) {
+                Ok(Some(_)) => return Ok(Some(candidate)),
+                // A file that cannot be read as a ref is not one; keep looking
+                Ok(None) | Err(Error::InvalidRef(_)) | Err(Error::Io(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(None)
+    }
+
+    // The object a ref ends up at once annotated tags are peeled, taken from
+    // packed-refs when it was recorded there
+    pub fn peeled_ref(&self, name: &str) -> Result<Option<ObjectId>> {
+        let (name, id) = self.resolve_ref(name)?;
+        let Some(mut id) = id else {
+            return Ok(None);
+        };
+        if let Some((packed_id, Some(peeled))) = self.packed_refs()?.refs.get(&name) {
+            if *packed_id == id {
+                return Ok(Some(*peeled));
+            }
+        }
+
+        loop {
+            let (object_type, content) = self.odb().read(&id)?;
+            match object_type.as_str() {
+                "tag" => id = Tag::parse(&content)?.object,
+                _ => return Ok(Some(id)),
+            }
+        }
+    }
+
+    // The branch HEAD points at, or None when it is detached
+    pub fn head_branch(&self) -> Result<Option<String>> {
+        Ok(match self.read_ref_target("HEAD")? {
+            Some(RefTarget::Symbolic(target)) => Some(
+                target
+                    .strip_prefix("refs/heads/")
+                    .unwrap_or(&target)
+                    .to_string(),
+            ),
+            _ => None,
+        })
+    }
+
+    // Point the ref `name` resolves to at `new_id`, provided it still holds
+    // `old_id` (None for a ref that must not exist yet), and log the move for it,
+    // for `name` if that is a symbolic ref, and for HEAD if the ref is checked out
+    pub fn update_ref(
+        &self,
+        name: &str,
+        new_id: &ObjectId,
+        old_id: Option<&ObjectId>,
+        reflog_message: &str,
+    ) -> Result<()> {
+        let mut names = vec![name.to_string()];
+        let (target, _) = self.resolve_ref(name)?;
+        if target != name {
+            names.push(target.clone());
+        }
+        // Moving the checked out branch moves HEAD too
+        if !names.iter().any(|name| name == "HEAD")
+            && self.read_ref_target("HEAD")? == Some(RefTarget::Symbolic(target.clone()))
+        {
+            names.push("HEAD".to_string());
+        }
+        check_writable_ref_name(&target)?;
+
+        let path = self.git_dir().join(&target);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Only compare once the lock keeps other writers out
+        let lock = LockFile::acquire(&path)?;
+        let current = self.read_ref(&target)?;
+        if current.as_ref() != old_id {
+            let reason = match (current, old_id) {
+                (Some(current), Some(old_id)) => {
+                    format!("is at {} but expected {}", current, old_id)
+                }
+                (Some(_), None) => "reference already exists".to_string(),
+                (None, _) => "unable to resolve reference".to_string(),
+            };
+            return Err(Error::RefLockFailed {
+                name: target,
+                reason,
+            });
+        }
+        lock.commit(format!("{}\n", new_id).as_bytes())?;
+
+        for name in names {
+            self.append_reflog(&name, old_id, new_id, reflog_message)?;
+        }
+        Ok(())
+    }
+
+    // Make `name` a symbolic ref pointing at `target`, as HEAD points at a branch
+    pub fn write_symbolic_ref(&self, name: &str, target: &str) -> Result<()> {
+        check_writable_ref_name(name)?;
+        if !target.starts_with("refs/") || !is_valid_ref_name(target) {
+            return Err(Error::InvalidRef(target.to_string()));
+        }
+        let path = self.git_dir().join(name);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        LockFile::acquire(&path)?.commit(format!("ref: {}\n", target).as_bytes())
+    }
+
+    // Helper function to record a ref move in logs/<name>. Like git, HEAD and
+    // branches are logged unless core.logAllRefUpdates says otherwise, and other
+    // refs only once their log exists.
+    fn append_reflog(
+        &self,
+        name: &str,
+        old_id: Option<&ObjectId>,
+        new_id: &ObjectId,
+        message: &str,
+    ) -> Result<()> {
+        let log_path = self.git_dir().join("logs").join(name);
+        let config = self.config()?;
+        let log_all = config
+            .get_bool("core.logallrefupdates")
+            .unwrap_or(self.work_tree().is_some());
+        let wanted = name == "HEAD" || name.starts_with("refs/heads/");
+        if !(log_path.exists() || log_all && wanted) {
+            return Ok(());
+        }
+
+        if let Some(dir) = log_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let old_hex = old_id.map_or_else(|| "0".repeat(ObjectId::HEX_LEN), ObjectId::to_hex);
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)?;
+        writeln!(
+            file,
+            "{} {} {}\t{}",
+            old_hex,
+            new_id,
+            reflog_signature(&config)?,
+            message
+        )?;
+        Ok(())
+    }
+}
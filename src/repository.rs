@@ -0,0 +1,1385 @@
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::ident::{identity, Role};
+use crate::ignore::IgnoreRules;
+use crate::index::{CacheTree, Index, IndexEntry};
+use crate::object::{Commit, ObjectId, Tag, Tree, TreeBuilder, TreeEntry};
+use crate::odb::{CompositeObjectDatabase, ObjectDatabase};
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+
+// A git repository: its .git directory, optional work tree and object store
+pub struct Repository {
+    git_dir: PathBuf,
+    work_tree: Option<PathBuf>,
+    odb: Box<dyn ObjectDatabase>,
+}
+
+// What ls-tree should list
+#[derive(Default)]
+pub struct LsTreeOptions {
+    // Descend into every subtree (-r)
+    pub recursive: bool,
+    // Show subtrees even while descending into them (-t)
+    pub show_trees: bool,
+    // Only list entries at, under or leading to these paths
+    pub paths: Vec<String>,
+}
+
+// Helper function to check whether ls-tree paths select an entry, either directly,
+// as something inside a selected directory, or as a directory leading to one
+fn path_is_selected(path: &[u8], paths: &[String]) -> bool {
+    paths.is_empty()
+        || paths.iter().map(String::as_bytes).any(|spec| {
+            let spec_dir = spec.strip_suffix(b"/").unwrap_or(spec);
+            path == spec_dir
+                || (path.starts_with(spec_dir) && path.get(spec_dir.len()) == Some(&b'/'))
+                || (spec.starts_with(path) && spec.get(path.len()) == Some(&b'/'))
+        })
+}
+
+// Helper function to check whether ls-tree should descend into a subtree
+fn should_descend(path: &[u8], options: &LsTreeOptions) -> bool {
+    options.recursive
+        || options.paths.iter().map(String::as_bytes).any(|spec| {
+            spec.len() > path.len() && spec.starts_with(path) && spec[path.len()] == b'/'
+        })
+}
+
+// How add chooses what to stage
+#[derive(Default)]
+pub struct AddOptions {
+    // Stage new, modified and deleted files everywhere when no path is given (-A)
+    pub all: bool,
+    // Only stage changes to files the index already tracks (-u)
+    pub update: bool,
+    // Report what would change without touching the index or the object store (-n)
+    pub dry_run: bool,
+    // Stage files even when the ignore rules exclude them (-f)
+    pub force: bool,
+}
+
+// How checkout copes with entries the file system cannot represent
+#[derive(Default)]
+pub struct CheckoutOptions {
+    // Warn and carry on instead of failing: symlinks fall back to plain files
+    // holding their target and entries with unknown modes are skipped
+    pub warn_unsupported: bool,
+}
+
+// How rm chooses what to remove
+#[derive(Default)]
+pub struct RmOptions {
+    // Only remove index entries, keeping the work tree files (--cached)
+    pub cached: bool,
+    // Allow directory paths to remove everything below them (-r)
+    pub recursive: bool,
+    // Skip the checks protecting uncommitted changes (-f)
+    pub force: bool,
+    // Report what would be removed without removing anything (-n)
+    pub dry_run: bool,
+}
+
+// A change add made to the index, or would make with --dry-run
+#[derive(Debug, PartialEq, Eq)]
+pub enum IndexChange {
+    Add(Vec<u8>),
+    Remove(Vec<u8>),
+}
+
+impl IndexChange {
+    pub fn path(&self) -> &[u8] {
+        match self {
+            IndexChange::Add(path) | IndexChange::Remove(path) => path,
+        }
+    }
+}
+
+// Helper function to check whether a path is a pathspec or lies below it; the
+// empty pathspec is the whole work tree
+fn matches_pathspec(path: &[u8], spec: &[u8]) -> bool {
+    spec.is_empty()
+        || path == spec
+        || (path.starts_with(spec) && path.get(spec.len()) == Some(&b'/'))
+}
+
+// Helper function to build git's rm refusal message for a group of files
+fn removal_refusal(reason: &str, hint: &str, paths: &[Vec<u8>]) -> String {
+    let subject = match paths.len() {
+        1 => "file has",
+        _ => "files have",
+    };
+    let mut message = format!("the following {} {}:\n", subject, reason);
+    for path in paths {
+        message.push_str(&format!("    {}\n", String::from_utf8_lossy(path)));
+    }
+    message.push_str(hint);
+    message
+}
+
+// Helper function to remove the directories a deleted file leaves empty, up to the work tree
+fn remove_empty_parents(work_tree: &Path, file: &Path) {
+    let mut dir = file.parent();
+    while let Some(current) = dir {
+        if current == work_tree || fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+}
+
+// Helper function to check whether a directory looks like a .git directory
+fn is_git_dir(path: &Path) -> bool {
+    path.join("HEAD").is_file() && path.join("objects").is_dir() && path.join("refs").is_dir()
+}
+
+// Helper function to check whether a work tree directory is a nested repository,
+// which is recorded as a gitlink rather than descended into
+fn is_nested_repository(dir: &Path) -> bool {
+    let dot_git = dir.join(".git");
+    dot_git.is_file() || is_git_dir(&dot_git)
+}
+
+// Helper function to pick the mode git stages a work tree entry with, from its
+// lstat data; None for plain directories and special files
+fn mode_from_metadata(full_path: &Path, metadata: &fs::Metadata) -> Option<u32> {
+    let file_type = metadata.file_type();
+    if file_type.is_symlink() {
+        Some(TreeEntry::MODE_SYMLINK)
+    } else if file_type.is_dir() {
+        is_nested_repository(full_path).then_some(TreeEntry::MODE_GITLINK)
+    } else if file_type.is_file() {
+        // Only the owner's execute bit counts, as in git
+        match metadata.mode() & 0o100 {
+            0 => Some(TreeEntry::MODE_BLOB),
+            _ => Some(TreeEntry::MODE_EXECUTABLE),
+        }
+    } else {
+        None
+    }
+}
+
+// Helper function to follow a "gitdir: <path>" file as used by worktrees and submodules
+fn read_gitfile(path: &Path) -> Result<PathBuf> {
+    let content = fs::read_to_string(path)?;
+    let target = content
+        .strip_prefix("gitdir: ")
+        .map(str::trim_end)
+        .ok_or_else(|| {
+            Error::NotARepository(format!("invalid gitfile format: {}", path.display()))
+        })?;
+
+    let target = Path::new(target);
+    Ok(match path.parent() {
+        Some(dir) if target.is_relative() => dir.join(target),
+        _ => target.to_path_buf(),
+    })
+}
+
+// Helper function to locate git's per-user config directory's parent,
+// $XDG_CONFIG_HOME or ~/.config
+pub(crate) fn xdg_config_home() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+}
+
+// Helper function to repair a message that claims to be UTF-8 the way git does:
+// every byte that is not part of a valid sequence is taken to be Latin-1
+fn latin1_fallback(message: &[u8]) -> Vec<u8> {
+    let mut repaired = Vec::with_capacity(message.len());
+    for chunk in message.utf8_chunks() {
+        repaired.extend_from_slice(chunk.valid().as_bytes());
+        for &b in chunk.invalid() {
+            repaired.extend_from_slice(char::from(b).encode_utf8(&mut [0; 2]).as_bytes());
+        }
+    }
+    repaired
+}
+
+// Helper function to make a detached ASCII-armored signature of `payload`
+// with gpg.program, the way git runs it; success is only trusted when gpg
+// reports SIG_CREATED on its status output
+fn sign_buffer(config: &Config, payload: &[u8], key: &str) -> Result<Vec<u8>> {
+    let program = config
+        .get_string("gpg.program")
+        .unwrap_or_else(|| "gpg".to_string());
+    let mut child = match Command::new(&program)
+        .args(["--status-fd=2", "-bsau", key])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(_) => return Err(Error::SigningFailed),
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // A gpg that exits early closes the pipe; its status tells the rest
+        let _ = stdin.write_all(payload);
+    }
+    let output = child.wait_with_output()?;
+
+    let status = String::from_utf8_lossy(&output.stderr);
+    let created = status
+        .lines()
+        .any(|line| line.starts_with("[GNUPG:] SIG_CREATED "));
+    match output.status.success() && created && !output.stdout.is_empty() {
+        true => Ok(output.stdout),
+        false => Err(Error::SigningFailed),
+    }
+}
+
+// Helper function to refuse a checkout path whose leading directories are
+// symlinks or anything but directories, as git's has_symlink_leading_path does,
+// so nothing is ever written outside the work tree
+fn check_leading_path(work_tree: &Path, path: &[u8]) -> Result<()> {
+    let mut dir = work_tree.to_path_buf();
+    let mut components: Vec<&[u8]> = path.split(|&b| b == b'/').collect();
+    components.pop();
+    for component in components {
+        dir.push(path_from_bytes(component));
+        match fs::symlink_metadata(&dir) {
+            Ok(metadata) if metadata.file_type().is_dir() => {}
+            Ok(_) => {
+                return Err(Error::InvalidTreeEntry {
+                    name: String::from_utf8_lossy(path).into_owned(),
+                    reason: "path is beyond a symbolic link",
+                })
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+// Helper function to create a checked out directory, refusing to reuse anything
+// that is not already a real directory
+fn create_checkout_dir(full_path: &Path, path: &[u8]) -> Result<()> {
+    match fs::symlink_metadata(full_path) {
+        Ok(metadata) if metadata.file_type().is_dir() => Ok(()),
+        Ok(_) => Err(Error::InvalidTreeEntry {
+            name: String::from_utf8_lossy(path).into_owned(),
+            reason: "path is already checked out as a file",
+        }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(fs::create_dir(full_path)?),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Helper function to clear the way for a checked out file: whatever file or
+// symlink is there goes, rather than being written through
+fn remove_checkout_file(full_path: &Path, path: &[u8]) -> Result<()> {
+    match fs::symlink_metadata(full_path) {
+        Ok(metadata) if metadata.file_type().is_dir() => Err(Error::InvalidTreeEntry {
+            name: String::from_utf8_lossy(path).into_owned(),
+            reason: "path is already checked out as a directory",
+        }),
+        Ok(_) => Ok(fs::remove_file(full_path)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Exclusive ownership of "<file>.lock" while a file is rewritten: the new
+// content only replaces the file on commit, and the lock goes away otherwise
+pub(crate) struct LockFile {
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: Option<fs::File>,
+}
+
+impl LockFile {
+    // Take the lock for `path`, failing if another writer holds it
+    pub(crate) fn acquire(path: &Path) -> Result<Self> {
+        let mut lock_path = path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+        let file = match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                return Err(Error::LockExists(lock_path.display().to_string()));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            lock_path,
+            file: Some(file),
+        })
+    }
+
+    // Replace the file with `content` and release the lock
+    pub(crate) fn commit(mut self, content: &[u8]) -> Result<()> {
+        let Some(mut file) = self.file.take() else {
+            return Ok(());
+        };
+        let written = file
+            .write_all(content)
+            .and_then(|_| file.sync_all())
+            .and_then(|_| fs::rename(&self.lock_path, &self.path));
+        if let Err(e) = written {
+            let _ = fs::remove_file(&self.lock_path);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        // Still holding the file means the new content never made it in place
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+// Helper function to replace a file through "<file>.lock", so readers see
+// either the old or the new content and concurrent writers fail
+pub(crate) fn write_locked(path: &Path, content: &[u8]) -> Result<()> {
+    LockFile::acquire(path)?.commit(content)
+}
+
+// Helper function to turn a path stored as bytes back into a filesystem path
+pub(crate) fn path_from_bytes(path: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(path))
+}
+
+// Recursive function collecting the files, symlinks and nested repositories below
+// a work tree directory, skipping ignored ones and never descending into ignored
+// directories
+fn collect_work_tree_files(
+    dir: &Path,
+    prefix: &[u8],
+    mut ignore: Option<&mut IgnoreRules>,
+    files: &mut Vec<Vec<u8>>,
+) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name == ".git" {
+            continue;
+        }
+
+        let mut path = prefix.to_vec();
+        path.extend_from_slice(name.as_encoded_bytes());
+        let full_path = entry.path();
+        let file_type = entry.file_type()?;
+        let is_dir = file_type.is_dir();
+        if let Some(ignore) = ignore.as_deref_mut() {
+            if ignore
+                .match_entry(&path, is_dir)?
+                .is_some_and(|pattern| !pattern.negated)
+            {
+                continue;
+            }
+        }
+
+        if is_dir && !is_nested_repository(&full_path) {
+            path.push(b'/');
+            collect_work_tree_files(&full_path, &path, ignore.as_deref_mut(), files)?;
+        } else if is_dir || file_type.is_file() || file_type.is_symlink() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// Helper function to read file content
+fn read_file_content(file_path: &Path) -> Result<Vec<u8>> {
+    Ok(fs::read(file_path)?)
+}
+
+impl Repository {
+    fn new(git_dir: PathBuf, work_tree: Option<PathBuf>) -> Self {
+        let odb = Box::new(CompositeObjectDatabase::open(&git_dir));
+        Self::with_object_database(git_dir, work_tree, odb)
+    }
+
+    // A repository whose objects live in `odb` rather than under `git_dir`, such
+    // as an InMemoryObjectDatabase in tests; refs, config and the index are still
+    // files in `git_dir`
+    pub fn with_object_database(
+        git_dir: impl Into<PathBuf>,
+        work_tree: Option<PathBuf>,
+        odb: Box<dyn ObjectDatabase>,
+    ) -> Self {
+        Self {
+            git_dir: git_dir.into(),
+            work_tree,
+            odb,
+        }
+    }
+
+    // Create the .git directory structure under `path`, keeping anything already there
+    pub fn init(path: impl AsRef<Path>) -> Result<Self> {
+        let work_tree = path.as_ref().to_path_buf();
+        let git_dir = work_tree.join(".git");
+
+        fs::create_dir_all(git_dir.join("objects"))?;
+        fs::create_dir_all(git_dir.join("refs/heads"))?;
+        let repo = Self::new(git_dir, Some(work_tree));
+        if !repo.git_dir.join("HEAD").exists() {
+            repo.write_symbolic_ref("HEAD", "refs/heads/main")?;
+        }
+
+        Ok(repo)
+    }
+
+    // Open the repository whose work tree or bare .git directory is exactly `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let dot_git = path.join(".git");
+
+        if dot_git.is_dir() && is_git_dir(&dot_git) {
+            Ok(Self::new(dot_git, Some(path.to_path_buf())))
+        } else if dot_git.is_file() {
+            Ok(Self::new(read_gitfile(&dot_git)?, Some(path.to_path_buf())))
+        } else if is_git_dir(path) {
+            Ok(Self::new(path.to_path_buf(), None))
+        } else {
+            Err(Error::NotARepository(path.display().to_string()))
+        }
+    }
+
+    // Find the repository containing `path` by walking up its parents, honoring
+    // GIT_DIR and GIT_WORK_TREE the way git does
+    pub fn discover(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let work_tree_override = env::var_os("GIT_WORK_TREE").map(PathBuf::from);
+
+        // An explicit GIT_DIR disables discovery; the work tree defaults to `path`
+        if let Some(git_dir) = env::var_os("GIT_DIR") {
+            let git_dir = path.join(git_dir);
+            if !is_git_dir(&git_dir) {
+                return Err(Error::NotARepository(format!("'{}'", git_dir.display())));
+            }
+            let work_tree = work_tree_override.map_or_else(|| path.to_path_buf(), |w| path.join(w));
+            return Ok(Self::new(git_dir, Some(work_tree)));
+        }
+
+        let start = path.canonicalize()?;
+        for dir in start.ancestors() {
+            if let Ok(mut repo) = Self::open(dir) {
+                if let Some(work_tree) = work_tree_override {
+                    repo.work_tree = Some(path.join(work_tree));
+                }
+                return Ok(repo);
+            }
+        }
+
+        Err(Error::RepositoryNotFound)
+    }
+
+    // The .git directory
+    pub fn git_dir(&self) -> &Path {
+        &self.git_dir
+    }
+
+    // The work tree, or None for a bare repository
+    pub fn work_tree(&self) -> Option<&Path> {
+        self.work_tree.as_deref()
+    }
+
+    // The object store, normally loose objects first and then packs
+    pub fn odb(&self) -> &dyn ObjectDatabase {
+        self.odb.as_ref()
+    }
+
+    // Read the staging index; a repository without one has nothing staged
+    pub fn read_index(&self) -> Result<Index> {
+        match fs::read(self.git_dir.join("index")) {
+            Ok(data) => Index::parse(&data),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Index::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Write the staging index through index.lock so readers never see a partial file
+    pub fn write_index(&self, index: &Index) -> Result<()> {
+        write_locked(&self.git_dir.join("index"), &index.serialize())
+    }
+
+    // Helper function to get the work tree or fail for bare repositories
+    pub(crate) fn require_work_tree(&self) -> Result<&Path> {
+        self.work_tree().ok_or(Error::BareRepository)
+    }
+
+    // Turn a command-line path, relative to the current directory, into a path
+    // relative to the work tree root as stored in the index
+    pub fn pathspec_to_path(&self, spec: &str) -> Result<Vec<u8>> {
+        let work_tree = self.require_work_tree()?.canonicalize()?;
+        let absolute = env::current_dir()?.canonicalize()?.join(spec);
+
+        // Resolve "." and ".." lexically so paths to deleted files still work
+        let mut normalized = PathBuf::new();
+        for component in absolute.components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    normalized.pop();
+                }
+                other => normalized.push(other),
+            }
+        }
+
+        let relative = normalized
+            .strip_prefix(&work_tree)
+            .map_err(|_| Error::OutsideRepository(spec.to_string()))?;
+        Ok(relative.as_os_str().as_encoded_bytes().to_vec())
+    }
+
+    // Paths of every file in the work tree relative to its root, skipping .git
+    // and whatever the ignore rules exclude
+    pub fn work_tree_files(&self) -> Result<Vec<Vec<u8>>> {
+        let mut ignore = self.ignore_rules()?;
+        let mut files = Vec::new();
+        collect_work_tree_files(
+            self.require_work_tree()?,
+            b"",
+            Some(&mut ignore),
+            &mut files,
+        )?;
+        files.sort();
+        Ok(files)
+    }
+
+    // The blobs HEAD's tree records, by path; empty before the first commit
+    pub(crate) fn head_entries(&self) -> Result<HashMap<Vec<u8>, (u32, ObjectId)>> {
+        let Some(head) = self.read_ref("HEAD")? else {
+            return Ok(HashMap::new());
+        };
+        let options = LsTreeOptions {
+            recursive: true,
+            ..LsTreeOptions::default()
+        };
+        Ok(self
+            .ls_tree(&self.peel_to_tree(&head)?, &options)?
+            .into_iter()
+            .map(|(path, entry)| (path, (entry.mode, entry.id)))
+            .collect())
+    }
+
+    // The mode a work tree path would be staged with and its lstat data, or None
+    // when nothing stageable is there
+    pub(crate) fn work_tree_entry(&self, path: &[u8]) -> Result<Option<(u32, fs::Metadata)>> {
+        let full_path = self.require_work_tree()?.join(path_from_bytes(path));
+        let metadata = match fs::symlink_metadata(&full_path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(mode_from_metadata(&full_path, &metadata).map(|mode| (mode, metadata)))
+    }
+
+    // Hash a work tree entry staged with `mode`, storing it unless only the name is
+    // wanted: symlinks hash their target and nested repositories name their HEAD commit
+    pub(crate) fn hash_work_tree_entry(
+        &self,
+        path: &[u8],
+        mode: u32,
+        write: bool,
+    ) -> Result<ObjectId> {
+        let full_path = self.require_work_tree()?.join(path_from_bytes(path));
+        let content = match mode {
+            TreeEntry::MODE_GITLINK => {
+                let head = Self::open(&full_path)?.read_ref("HEAD")?;
+                return head.ok_or_else(|| {
+                    Error::GitlinkWithoutCommit(format!("{}/", String::from_utf8_lossy(path)))
+                });
+            }
+            TreeEntry::MODE_SYMLINK => fs::read_link(&full_path)?
+                .into_os_string()
+                .into_encoded_bytes(),
+            _ => read_file_content(&full_path)?,
+        };
+        match write {
+            true => self.odb.write("blob", &content),
+            false => Ok(ObjectId::hash_object("blob", &content)),
+        }
+    }
+
+    // Helper function for add command: stage the files matching the pathspecs.
+    // Also returns the paths named explicitly but left out as ignored.
+    pub fn add_paths(
+        &self,
+        pathspecs: &[String],
+        options: &AddOptions,
+    ) -> Result<(Vec<IndexChange>, Vec<Vec<u8>>)> {
+        let work_tree = self.require_work_tree()?;
+        let specs = match pathspecs.is_empty() {
+            true => vec![Vec::new()],
+            false => pathspecs
+                .iter()
+                .map(|spec| self.pathspec_to_path(spec))
+                .collect::<Result<Vec<_>>>()?,
+        };
+        let matches_any = |path: &[u8]| specs.iter().any(|spec| matches_pathspec(path, spec));
+
+        let mut index = self.read_index()?;
+        let mut ignore = self.ignore_rules()?;
+        let mut files = Vec::new();
+        let walk_ignore = (!options.force).then_some(&mut ignore);
+        collect_work_tree_files(work_tree, b"", walk_ignore, &mut files)?;
+
+        // Tracked files stay staged even where the ignore rules would exclude them,
+        // and unpopulated submodules keep their entries
+        let mut unpopulated = BTreeSet::new();
+        for entry in &index.entries {
+            if self.work_tree_entry(&entry.path)?.is_some() {
+                files.push(entry.path.clone());
+            } else if entry.mode == TreeEntry::MODE_GITLINK
+                && work_tree.join(path_from_bytes(&entry.path)).is_dir()
+            {
+                unpopulated.insert(entry.path.clone());
+            }
+        }
+        files.retain(|path| matches_any(path));
+        files.sort();
+        files.dedup();
+
+        let mut ignored = Vec::new();
+        for (spec, original) in specs.iter().zip(pathspecs) {
+            let matched = files.iter().any(|path| matches_pathspec(path, spec))
+                || index
+                    .entries
+                    .iter()
+                    .any(|entry| matches_pathspec(&entry.path, spec));
+            if matched {
+                continue;
+            }
+
+            let is_dir = work_tree.join(path_from_bytes(spec)).is_dir();
+            match ignore.matching(spec, is_dir)? {
+                Some(found) if !found.pattern.negated && !options.force && !spec.is_empty() => {
+                    ignored.push(found.path)
+                }
+                _ => return Err(Error::PathspecNoMatch(original.clone())),
+            }
+        }
+        ignored.sort();
+        ignored.dedup();
+
+        let mut changes = Vec::new();
+        for path in &files {
+            let tracked = index.entry(path, 0).cloned();
+            if options.update && tracked.is_none() {
+                continue;
+            }
+
+            let Some((mode, metadata)) = self.work_tree_entry(path)? else {
+                continue;
+            };
+            // A nested repository's stat data says nothing about its HEAD
+            if tracked.as_ref().is_some_and(|entry| {
+                entry.mode == mode
+                    && mode != TreeEntry::MODE_GITLINK
+                    && entry.stat_matches(&metadata)
+            }) {
+                continue;
+            }
+
+            let id = self.hash_work_tree_entry(path, mode, !options.dry_run)?;
+            if !tracked
+                .as_ref()
+                .is_some_and(|entry| entry.id == id && entry.mode == mode)
+            {
+                changes.push(IndexChange::Add(path.clone()));
+            }
+
+            index.add(IndexEntry::from_metadata(path.clone(), mode, id, &metadata));
+        }
+
+        // Tracked files that are gone from the work tree are staged as deletions
+        let existing: BTreeSet<&[u8]> = files.iter().map(Vec::as_slice).collect();
+        let deleted: Vec<Vec<u8>> = index
+            .entries
+            .iter()
+            .filter(|entry| {
+                matches_any(&entry.path)
+                    && !existing.contains(entry.path.as_slice())
+                    && !unpopulated.contains(entry.path.as_slice())
+            })
+            .map(|entry| entry.path.clone())
+            .collect();
+        for path in deleted {
+            if index.remove(&path) {
+                changes.push(IndexChange::Remove(path));
+            }
+        }
+
+        if !options.dry_run {
+            self.write_index(&index)?;
+        }
+        changes.sort_by(|a, b| a.path().cmp(b.path()));
+        Ok((changes, ignored))
+    }
+
+    // Helper function for rm command: unstage the matching paths and, unless
+    // --cached, delete them from the work tree; returns the removed paths
+    pub fn rm_paths(&self, pathspecs: &[String], options: &RmOptions) -> Result<Vec<Vec<u8>>> {
+        let work_tree = self.require_work_tree()?;
+        let mut index = self.read_index()?;
+
+        let mut removed = BTreeSet::new();
+        for original in pathspecs {
+            let spec = self.pathspec_to_path(original)?;
+            let matched: Vec<&IndexEntry> = index
+                .entries
+                .iter()
+                .filter(|entry| matches_pathspec(&entry.path, &spec))
+                .collect();
+            if matched.is_empty() {
+                return Err(Error::PathspecNoMatch(original.clone()));
+            }
+            if !options.recursive && matched.iter().any(|entry| entry.path != spec) {
+                return Err(Error::RecursiveRemovalRequired(original.clone()));
+            }
+            removed.extend(matched.into_iter().map(|entry| entry.path.clone()));
+        }
+
+        if !options.force {
+            self.check_removable(&index, &removed, options.cached)?;
+        }
+
+        for path in &removed {
+            index.remove(path);
+            if !options.cached && !options.dry_run {
+                let file = work_tree.join(path_from_bytes(path));
+                match fs::remove_file(&file) {
+                    Ok(()) => remove_empty_parents(work_tree, &file),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+
+        if !options.dry_run {
+            self.write_index(&index)?;
+        }
+        Ok(removed.into_iter().collect())
+    }
+
+    // Refuse to rm files whose staged or work tree content would be lost, as git does
+    fn check_removable(
+        &self,
+        index: &Index,
+        paths: &BTreeSet<Vec<u8>>,
+        cached: bool,
+    ) -> Result<()> {
+        let head = self.head_entries()?;
+
+        let mut staged_and_local = Vec::new();
+        let mut staged = Vec::new();
+        let mut local = Vec::new();
+        for path in paths {
+            let Some(entry) = index.entry(path, 0) else {
+                continue;
+            };
+
+            let staged_changes = head.get(path) != Some(&(entry.mode, entry.id));
+            let local_changes = match self.work_tree_entry(path)? {
+                Some((mode, _)) if mode != entry.mode => true,
+                Some((mode, metadata)) => {
+                    !entry.stat_matches(&metadata)
+                        && self.hash_work_tree_entry(path, mode, false)? != entry.id
+                }
+                None => false,
+            };
+
+            if staged_changes && local_changes {
+                staged_and_local.push(path.clone());
+            } else if !cached {
+                if staged_changes {
+                    staged.push(path.clone());
+                }
+                if local_changes {
+                    local.push(path.clone());
+                }
+            }
+        }
+
+        let mut refusals = Vec::new();
+        if !staged_and_local.is_empty() {
+            refusals.push(removal_refusal(
+                "staged content different from both the\nfile and the HEAD",
+                "(use -f to force removal)",
+                &staged_and_local,
+            ));
+        }
+        if !staged.is_empty() {
+            refusals.push(removal_refusal(
+                "changes staged in the index",
+                "(use --cached to keep the file, or -f to force removal)",
+                &staged,
+            ));
+        }
+        if !local.is_empty() {
+            refusals.push(removal_refusal(
+                "local modifications",
+                "(use --cached to keep the file, or -f to force removal)",
+                &local,
+            ));
+        }
+
+        match refusals.is_empty() {
+            true => Ok(()),
+            false => Err(Error::RemovalRefused(refusals.join("\nerror: "))),
+        }
+    }
+
+    // Helper function for hash-object -w
+    pub fn write_object(&self, object_type: &str, content: &[u8]) -> Result<ObjectId> {
+        self.odb.write(object_type, content)
+    }
+
+    // Helper function for cat-file -t and -s: an object's type and size
+    pub fn read_object_header(&self, object_id: &ObjectId) -> Result<(String, usize)> {
+        self.odb.read_header(object_id)
+    }
+
+    // Helper function for cat-file -e
+    pub fn has_object(&self, object_id: &ObjectId) -> Result<bool> {
+        self.odb.exists(object_id)
+    }
+
+    // Helper function for cat-file --batch: an object's type and content, or None if missing
+    pub fn try_read_object(&self, object_id: &ObjectId) -> Result<Option<(String, Vec<u8>)>> {
+        self.odb.try_read(object_id)
+    }
+
+    // Helper function for cat-file --batch-check: an object's type and size, or None if missing
+    pub fn try_read_object_header(&self, object_id: &ObjectId) -> Result<Option<(String, usize)>> {
+        self.odb.try_read_header(object_id)
+    }
+
+    // Helper function for cat-file --batch-all-objects: every object name, sorted
+    pub fn all_object_ids(&self) -> Result<Vec<ObjectId>> {
+        self.odb.list()
+    }
+
+    // Helper function for cat-file -p: trees are listed like ls-tree, everything
+    // else is returned byte for byte
+    pub fn cat_file_object(&self, object_id: &ObjectId) -> Result<Vec<u8>> {
+        let (object_type, content) = self.odb.read(object_id)?;
+        if object_type != "tree" {
+            return Ok(content);
+        }
+
+        let mut output = Vec::new();
+        for entry in Tree::parse(&content)?.entries {
+            output.extend_from_slice(
+                format!("{:06o} {} {}\t", entry.mode, entry.object_type(), entry.id).as_bytes(),
+            );
+            output.extend_from_slice(&entry.name);
+            output.push(b'\n');
+        }
+        Ok(output)
+    }
+
+    // Read and parse a tree object
+    pub fn read_tree(&self, tree_id: &ObjectId) -> Result<Tree> {
+        let (object_type, content) = self.odb.read(tree_id)?;
+        if object_type != "tree" {
+            return Err(Error::UnexpectedObjectType {
+                id: *tree_id,
+                expected: "tree",
+                actual: object_type,
+            });
+        }
+        Tree::parse(&content)
+    }
+
+    // Read and parse a commit object
+    pub fn read_commit(&self, commit_id: &ObjectId) -> Result<Commit> {
+        let (object_type, content) = self.odb.read(commit_id)?;
+        if object_type != "commit" {
+            return Err(Error::UnexpectedObjectType {
+                id: *commit_id,
+                expected: "commit",
+                actual: object_type,
+            });
+        }
+        Commit::parse(&content)
+    }
+
+    // Resolve a revision: a full object name, a ref name as git abbreviates it,
+    // or an unambiguous abbreviated object name
+    pub fn resolve_revision(&self, name: &str) -> Result<ObjectId> {
+        if let Ok(id) = ObjectId::from_hex(name) {
+            return Ok(id);
+        }
+
+        if let Some(full_name) = self.dwim_ref(name)? {
+            if let Some(id) = self.read_ref(&full_name)? {
+                return Ok(id);
+            }
+        }
+
+        if name.len() >= 4 && name.bytes().all(|b| b.is_ascii_hexdigit()) {
+            let mut matches = self
+                .odb
+                .list()?
+                .into_iter()
+                .filter(|id| id.starts_with_hex(name));
+            match (matches.next(), matches.next()) {
+                (Some(id), None) => return Ok(id),
+                (Some(_), Some(_)) => {
+                    return Err(Error::InvalidObjectName(format!(
+                        "{} (short object ID is ambiguous)",
+                        name
+                    )));
+                }
+                _ => {}
+            }
+        }
+
+        Err(Error::InvalidObjectName(name.to_string()))
+    }
+
+    // Follow tags and commits down to the tree they name
+    pub fn peel_to_tree(&self, object_id: &ObjectId) -> Result<ObjectId> {
+        let mut id = *object_id;
+        loop {
+            let (object_type, content) = self.odb.read(&id)?;
+            match object_type.as_str() {
+                "tree" => return Ok(id),
+                "commit" => return Ok(Commit::parse(&content)?.tree),
+                "tag" => id = Tag::parse(&content)?.object,
+                _ => {
+                    return Err(Error::UnexpectedObjectType {
+                        id,
+                        expected: "tree",
+                        actual: object_type,
+                    });
+                }
+            }
+        }
+    }
+
+    // Helper function for ls-tree command: the selected entries with their full paths
+    pub fn ls_tree(
+        &self,
+        tree_id: &ObjectId,
+        options: &LsTreeOptions,
+    ) -> Result<Vec<(Vec<u8>, TreeEntry)>> {
+        let mut listed = Vec::new();
+        self.ls_tree_recursive(tree_id, b"", options, &mut listed)?;
+        Ok(listed)
+    }
+
+    // Recursive function for ls-tree
+    fn ls_tree_recursive(
+        &self,
+        tree_id: &ObjectId,
+        prefix: &[u8],
+        options: &LsTreeOptions,
+        listed: &mut Vec<(Vec<u8>, TreeEntry)>,
+    ) -> Result<()> {
+        for entry in self.read_tree(tree_id)?.entries {
+            let mut path = prefix.to_vec();
+            path.extend_from_slice(&entry.name);
+            if !path_is_selected(&path, &options.paths) {
+                continue;
+            }
+
+            if entry.is_tree() && should_descend(&path, options) {
+                let mut subtree_prefix = path.clone();
+                subtree_prefix.push(b'/');
+                let subtree_id = entry.id;
+                if options.show_trees {
+                    listed.push((path, entry));
+                }
+                self.ls_tree_recursive(&subtree_id, &subtree_prefix, options, listed)?;
+            } else {
+                listed.push((path, entry));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Recursive function for write-tree: build the tree for index entries sharing the
+    // first `base_len` bytes of their paths, reusing cached trees that are still valid
+    fn write_tree_from_entries(
+        &self,
+        entries: &[IndexEntry],
+        base_len: usize,
+        cached: Option<&CacheTree>,
+        missing_ok: bool,
+    ) -> Result<CacheTree> {
+        let mut tree = TreeBuilder::new();
+        let mut subtrees = Vec::new();
+
+        // Intent-to-add entries are left out, and keep the cached tree invalid
+        let mut valid = true;
+
+        let mut i = 0;
+        while i < entries.len() {
+            let entry = &entries[i];
+            let rest = &entry.path[base_len..];
+
+            let Some(slash) = rest.iter().position(|&b| b == b'/') else {
+                i += 1;
+                if entry.intent_to_add() {
+                    valid = false;
+                    continue;
+                }
+                if !missing_ok
+                    && entry.mode != TreeEntry::MODE_GITLINK
+                    && !self.odb.exists(&entry.id)?
+                {
+                    return Err(Error::InvalidIndexObject {
+                        mode: entry.mode,
+                        id: entry.id,
+                        path: entry.path_lossy().into_owned(),
+                    });
+                }
+                tree.insert(entry.mode, rest, entry.id)?;
+                continue;
+            };
+
+            // Index order keeps each directory's entries together
+            let dir = &rest[..=slash];
+            let end = i + entries[i..]
+                .iter()
+                .take_while(|entry| entry.path[base_len..].starts_with(dir))
+                .count();
+            let name = &rest[..slash];
+
+            if entries[i..end].iter().all(IndexEntry::intent_to_add) {
+                valid = false;
+                i = end;
+                continue;
+            }
+
+            let cached_subtree = cached.and_then(|cached| cached.subtree(name));
+            let subtree = match cached_subtree {
+                Some(subtree) if subtree.is_valid() && self.odb.exists(&subtree.id)? => {
+                    subtree.clone()
+                }
+                _ => CacheTree {
+                    name: name.to_vec(),
+                    ..self.write_tree_from_entries(
+                        &entries[i..end],
+                        base_len + slash + 1,
+                        cached_subtree,
+                        missing_ok,
+                    )?
+                },
+            };
+            valid &= subtree.is_valid();
+            tree.insert(TreeEntry::MODE_TREE, name, subtree.id)?;
+            subtrees.push(subtree);
+            i = end;
+        }
+
+        // git keeps cached subtrees ordered by name length, then by name
+        subtrees.sort_by(|a, b| (a.name.len(), &a.name).cmp(&(b.name.len(), &b.name)));
+
+        Ok(CacheTree {
+            name: Vec::new(),
+            entry_count: match valid {
+                true => entries.len() as i32,
+                false => -1,
+            },
+            id: self.odb.write("tree", &tree.build().serialize())?,
+            subtrees,
+        })
+    }
+
+    // Helper function for write-tree command: write the index as trees, optionally
+    // returning the tree of a subdirectory instead of the root
+    pub fn write_tree(&self, prefix: Option<&str>, missing_ok: bool) -> Result<ObjectId> {
+        let mut index = self.read_index()?;
+        if let Some(entry) = index.entries.iter().find(|entry| entry.stage() != 0) {
+            return Err(Error::Unmerged(entry.path_lossy().into_owned()));
+        }
+
+        let root = match &index.cache_tree {
+            Some(cached) if cached.is_valid() && self.odb.exists(&cached.id)? => cached.clone(),
+            cached => {
+                self.write_tree_from_entries(&index.entries, 0, cached.as_ref(), missing_ok)?
+            }
+        };
+
+        // Save the refreshed cache for next time; another process holding the
+        // lock only costs us the cache
+        if index.cache_tree.as_ref() != Some(&root) {
+            index.cache_tree = Some(root.clone());
+            match self.write_index(&index) {
+                Ok(()) | Err(Error::LockExists(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        match prefix {
+            None => Ok(root.id),
+            Some(prefix) => root
+                .find(prefix.as_bytes())
+                .filter(|tree| tree.is_valid())
+                .map(|tree| tree.id)
+                .ok_or_else(|| Error::PrefixNotFound(prefix.to_string())),
+        }
+    }
+
+    // Helper function to check that an object exists and has the expected type
+    fn require_object_type(&self, id: &ObjectId, expected: &'static str) -> Result<()> {
+        let (object_type, _) = match self.odb.read_header(id) {
+            Ok(header) => header,
+            Err(Error::ObjectNotFound(_)) => return Err(Error::InvalidObject(*id)),
+            Err(e) => return Err(e),
+        };
+        match object_type == expected {
+            true => Ok(()),
+            false => Err(Error::InvalidTypedObject { id: *id, expected }),
+        }
+    }
+
+    // Helper function for commit-tree command: the message is used byte for
+    // byte, extra headers are written after the committer and a signing key
+    // adds a gpgsig header (an empty key means the configured one)
+    pub fn create_commit(
+        &self,
+        tree_id: &ObjectId,
+        parents: &[ObjectId],
+        message: &[u8],
+        extra_headers: &[(String, Vec<u8>)],
+        sign_key: Option<&str>,
+    ) -> Result<ObjectId> {
+        self.require_object_type(tree_id, "tree")?;
+        for parent in parents {
+            self.require_object_type(parent, "commit")?;
+        }
+        self.write_commit(tree_id, parents, None, message, extra_headers, sign_key)
+    }
+
+    // Write a commit by the current committer; the author is the current one too
+    // unless an existing author line is kept, as when amending
+    pub(crate) fn write_commit(
+        &self,
+        tree_id: &ObjectId,
+        parents: &[ObjectId],
+        author: Option<&[u8]>,
+        message: &[u8],
+        extra_headers: &[(String, Vec<u8>)],
+        sign_key: Option<&str>,
+    ) -> Result<ObjectId> {
+        let config = self.config()?;
+        let author = match author {
+            Some(author) => author.to_vec(),
+            None => identity(&config, Role::Author, true)?
+                .to_string()
+                .into_bytes(),
+        };
+        let committer = identity(&config, Role::Committer, true)?;
+
+        // Messages in anything but UTF-8 name their encoding after the committer,
+        // unless the caller already gave one
+        let mut headers = Vec::new();
+        let mut message = message.to_vec();
+        let encoding = config.get_string("i18n.commitencoding");
+        match encoding.filter(|encoding| {
+            !encoding.eq_ignore_ascii_case("utf-8") && !encoding.eq_ignore_ascii_case("utf8")
+        }) {
+            Some(_) if extra_headers.iter().any(|(key, _)| key == "encoding") => {}
+            Some(encoding) => headers.push(("encoding".to_string(), encoding.into_bytes())),
+            None if std::str::from_utf8(&message).is_err() => {
+                eprintln!("Warning: commit message did not conform to UTF-8.");
+                eprintln!("You may want to amend it after fixing the message, or set the config");
+                eprintln!("variable i18n.commitEncoding to the encoding your project uses.");
+                message = latin1_fallback(&message);
+            }
+            None => {}
+        }
+        headers.extend_from_slice(extra_headers);
+
+        let mut commit = Commit {
+            tree: *tree_id,
+            parents: parents.to_vec(),
+            author,
+            committer: committer.to_string().into_bytes(),
+            extra_headers: headers,
+            message,
+        };
+
+        // The signature covers the commit as it is so far and goes last among
+        // the headers; without a key of its own the committer signs
+        if let Some(key) = sign_key {
+            let key = match key {
+                "" => config
+                    .get_string("user.signingkey")
+                    .unwrap_or_else(|| format!("{} <{}>", committer.name, committer.email)),
+                key => key.to_string(),
+            };
+            let mut signature = sign_buffer(&config, &commit.serialize(), &key)?;
+            if signature.last() == Some(&b'\n') {
+                signature.pop();
+            }
+            commit.extra_headers.push(("gpgsig".to_string(), signature));
+        }
+
+        self.odb.write("commit", &commit.serialize())
+    }
+
+    // Render tree recursively to working directory, collecting an index entry
+    // for everything written
+    fn render_tree(
+        &self,
+        dir: &Path,
+        prefix: &[u8],
+        tree_id: &ObjectId,
+        options: &CheckoutOptions,
+        checked_out: &mut Vec<IndexEntry>,
+    ) -> Result<()> {
+        let tree = self.read_tree(tree_id)?;
+        // Names come from whoever made the tree, so never let one leave `dir`,
+        // reach into .git or appear twice
+        tree.check_entries()?;
+        let work_tree = self.require_work_tree()?;
+
+        for entry in &tree.entries {
+            let full_path = dir.join(path_from_bytes(&entry.name));
+            let mut path = prefix.to_vec();
+            path.extend_from_slice(&entry.name);
+            check_leading_path(work_tree, &path)?;
+
+            match entry.mode {
+                TreeEntry::MODE_TREE => {
+                    create_checkout_dir(&full_path, &path)?;
+                    path.push(b'/');
+                    self.render_tree(&full_path, &path, &entry.id, options, checked_out)?;
+                    continue;
+                }
+                TreeEntry::MODE_BLOB | TreeEntry::MODE_EXECUTABLE => {
+                    let (_, content) = self.odb.read(&entry.id)?;
+                    // Permissions go through the umask, as when git creates files
+                    let permissions = match entry.mode {
+                        TreeEntry::MODE_EXECUTABLE => 0o777,
+                        _ => 0o666,
+                    };
+                    remove_checkout_file(&full_path, &path)?;
+                    fs::OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .custom_flags(libc::O_NOFOLLOW)
+                        .mode(permissions)
+                        .open(&full_path)?
+                        .write_all(&content)?;
+                }
+                TreeEntry::MODE_SYMLINK => {
+                    remove_checkout_file(&full_path, &path)?;
+                    let (_, target) = self.odb.read(&entry.id)?;
+                    if let Err(e) = std::os::unix::fs::symlink(path_from_bytes(&target), &full_path)
+                    {
+                        if !options.warn_unsupported {
+                            return Err(e.into());
+                        }
+                        // Like git without core.symlinks, fall back to a file holding the target
+                        eprintln!(
+                            "warning: unable to create symlink '{}': {}",
+                            String::from_utf8_lossy(&path),
+                            e
+                        );
+                        fs::OpenOptions::new()
+                            .write(true)
+                            .create_new(true)
+                            .custom_flags(libc::O_NOFOLLOW)
+                            .open(&full_path)?
+                            .write_all(&target)?;
+                    }
+                }
+                TreeEntry::MODE_GITLINK => {
+                    // Submodules are left unpopulated, as an empty directory
+                    create_checkout_dir(&full_path, &path)?;
+                }
+                mode => {
+                    let message = format!(
+                        "cannot check out '{}' with mode {:06o}",
+                        String::from_utf8_lossy(&path),
+                        mode
+                    );
+                    if !options.warn_unsupported {
+                        return Err(Error::Unsupported(message));
+                    }
+                    eprintln!("warning: {}", message);
+                    continue;
+                }
+            }
+
+            let metadata = fs::symlink_metadata(&full_path)?;
+            checked_out.push(IndexEntry::from_metadata(
+                path, entry.mode, entry.id, &metadata,
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Helper function to create working directory from commit, staging what
+    // was checked out in a fresh index
+    pub fn create_working_directory_from_commit(
+        &self,
+        head_commit: &ObjectId,
+        options: &CheckoutOptions,
+    ) -> Result<()> {
+        // Read the commit object
+        let commit = self.read_commit(head_commit)?;
+
+        // Render the tree to working directory
+        let mut checked_out = Vec::new();
+        let work_tree = self.require_work_tree()?;
+        fs::create_dir_all(work_tree)?;
+        self.render_tree(work_tree, b"", &commit.tree, options, &mut checked_out)?;
+
+        let mut index = Index::default();
+        for entry in checked_out {
+            index.add(entry);
+        }
+        self.write_index(&index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::odb::InMemoryObjectDatabase;
+    use crate::test_util::ScratchDir;
+
+    #[test]
+    fn keeps_objects_in_the_database_it_is_given() {
+        let dir = ScratchDir::new("repository-in-memory", &[]);
+        let repo = Repository::with_object_database(
+            dir.join(".git"),
+            Some(dir.path().to_path_buf()),
+            Box::new(InMemoryObjectDatabase::new()),
+        );
+
+        let blob = repo.odb().write("blob", b"hello\n").unwrap();
+        let mut builder = TreeBuilder::new();
+        builder.insert(TreeEntry::MODE_BLOB, "hello", blob).unwrap();
+        let tree = repo
+            .odb()
+            .write("tree", &builder.build().serialize())
+            .unwrap();
+
+        // Short names are looked up in the injected store too
+        let resolved = repo.resolve_revision(&tree.abbrev(7)).unwrap();
+        assert_eq!(resolved, tree);
+        let listing = repo.ls_tree(&tree, &LsTreeOptions::default()).unwrap();
+        let names: Vec<_> = listing.iter().map(|(path, _)| path.as_slice()).collect();
+        assert_eq!(names, [b"hello"]);
+        assert!(!dir.join(".git/objects").exists());
+    }
+}
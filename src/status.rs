@@ -0,0 +1,246 @@
+use crate::error::Result;
+use crate::index::IndexEntry;
+use crate::object::{ObjectId, TreeEntry};
+use crate::repository::{path_from_bytes, Repository};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+
+// Which untracked files status reports
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UntrackedFiles {
+    No,
+    // Untracked directories collapse to a single "dir/" line
+    #[default]
+    Normal,
+    All,
+}
+
+// A tracked path whose HEAD, index or work tree versions differ. The change
+// columns use git's short status letters, with b' ' for no change.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StatusEntry {
+    pub path: Vec<u8>,
+    // HEAD against the index: A, M, D or T
+    pub staged: u8,
+    // The index against the work tree: M, D, T, or A for intent-to-add entries
+    pub unstaged: u8,
+    pub head: Option<(u32, ObjectId)>,
+    pub index: Option<(u32, ObjectId)>,
+    pub work_tree_mode: Option<u32>,
+}
+
+// A conflicted path and the versions recorded in merge stages 1 to 3
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnmergedEntry {
+    pub path: Vec<u8>,
+    pub stages: [Option<(u32, ObjectId)>; 3],
+    pub work_tree_mode: Option<u32>,
+}
+
+impl UnmergedEntry {
+    // git's two-letter code: which sides added, deleted or modified the path
+    pub fn code(&self) -> &'static str {
+        match (
+            self.stages[0].is_some(),
+            self.stages[1].is_some(),
+            self.stages[2].is_some(),
+        ) {
+            (true, false, false) => "DD",
+            (false, true, false) => "AU",
+            (true, true, false) => "UD",
+            (false, false, true) => "UA",
+            (true, false, true) => "DU",
+            (false, true, true) => "AA",
+            _ => "UU",
+        }
+    }
+}
+
+// What status found, with every list sorted by path
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Status {
+    // The checked out branch, or None when HEAD is detached
+    pub branch: Option<String>,
+    // The commit HEAD points at, or None before the first commit
+    pub head: Option<ObjectId>,
+    pub entries: Vec<StatusEntry>,
+    pub unmerged: Vec<UnmergedEntry>,
+    pub untracked: Vec<Vec<u8>>,
+}
+
+// Helper function to tell a change from a type change (file, symlink or gitlink)
+fn change_kind(old_mode: u32, new_mode: u32) -> u8 {
+    match old_mode & 0o170000 == new_mode & 0o170000 {
+        true => b'M',
+        false => b'T',
+    }
+}
+
+// Helper function to collapse untracked files into their topmost directory that
+// holds nothing tracked, the way git lists them by default
+fn collapse_untracked(files: Vec<Vec<u8>>, tracked: &BTreeSet<Vec<u8>>) -> Vec<Vec<u8>> {
+    let mut tracked_dirs = BTreeSet::new();
+    for path in tracked {
+        for (i, _) in path.iter().enumerate().filter(|(_, &b)| b == b'/') {
+            tracked_dirs.insert(&path[..=i]);
+        }
+    }
+
+    let mut collapsed = BTreeSet::new();
+    for file in files {
+        let untracked_dir = file
+            .iter()
+            .enumerate()
+            .filter(|(_, &b)| b == b'/')
+            .map(|(i, _)| &file[..=i])
+            .find(|dir| !tracked_dirs.contains(dir));
+        collapsed.insert(untracked_dir.map_or_else(|| file.clone(), <[u8]>::to_vec));
+    }
+    collapsed.into_iter().collect()
+}
+
+impl Repository {
+    // Compare a stage 0 index entry with the work tree: M, T, D or b' ' for unchanged
+    fn work_tree_change(
+        &self,
+        entry: &IndexEntry,
+        index_mtime: Option<(u32, u32)>,
+    ) -> Result<(u8, Option<u32>)> {
+        let Some((mode, metadata)) = self.work_tree_entry(&entry.path)? else {
+            // A submodule that was never populated is left as an empty directory
+            let full_path = self.require_work_tree()?.join(path_from_bytes(&entry.path));
+            if entry.mode == TreeEntry::MODE_GITLINK && full_path.is_dir() {
+                return Ok((b' ', Some(entry.mode)));
+            }
+            return Ok((b'D', None));
+        };
+
+        if entry.intent_to_add() {
+            return Ok((b'A', Some(mode)));
+        }
+        if mode != entry.mode {
+            return Ok((change_kind(entry.mode, mode), Some(mode)));
+        }
+        if mode == TreeEntry::MODE_GITLINK {
+            let head = self.hash_work_tree_entry(&entry.path, mode, false).ok();
+            return match head == Some(entry.id) {
+                true => Ok((b' ', Some(mode))),
+                false => Ok((b'M', Some(mode))),
+            };
+        }
+
+        // A file changed in the same instant the index was written can keep its
+        // stat data, so such racily clean entries are compared by content
+        let racy = index_mtime.is_some_and(|index_mtime| {
+            (entry.mtime_seconds, entry.mtime_nanoseconds) >= index_mtime
+        });
+        if entry.stat_matches(&metadata) && !racy {
+            return Ok((b' ', Some(mode)));
+        }
+
+        match self.hash_work_tree_entry(&entry.path, mode, false)? == entry.id {
+            true => Ok((b' ', Some(mode))),
+            false => Ok((b'M', Some(mode))),
+        }
+    }
+
+    // Helper function for status command: compare HEAD, the index and the work tree
+    pub fn status(&self, untracked_files: UntrackedFiles) -> Result<Status> {
+        let index = self.read_index()?;
+        let index_mtime = fs::metadata(self.git_dir().join("index"))
+            .ok()
+            .map(|metadata| (metadata.mtime() as u32, metadata.mtime_nsec() as u32));
+        let head_id = self.read_ref("HEAD")?;
+        let mut head = self.head_entries()?;
+
+        let mut status = Status {
+            branch: self.head_branch()?,
+            head: head_id,
+            ..Status::default()
+        };
+
+        let mut unmerged: BTreeMap<Vec<u8>, UnmergedEntry> = BTreeMap::new();
+        let mut tracked = BTreeSet::new();
+        for entry in &index.entries {
+            tracked.insert(entry.path.clone());
+            let head_entry = head.remove(&entry.path);
+
+            if entry.stage() != 0 {
+                let conflict =
+                    unmerged
+                        .entry(entry.path.clone())
+                        .or_insert_with(|| UnmergedEntry {
+                            path: entry.path.clone(),
+                            stages: [None; 3],
+                            work_tree_mode: None,
+                        });
+                conflict.stages[entry.stage() as usize - 1] = Some((entry.mode, entry.id));
+                continue;
+            }
+
+            let staged = match head_entry {
+                _ if entry.intent_to_add() => b' ',
+                None => b'A',
+                Some((mode, id)) if mode == entry.mode && id == entry.id => b' ',
+                Some((mode, _)) => change_kind(mode, entry.mode),
+            };
+            let (unstaged, work_tree_mode) = self.work_tree_change(entry, index_mtime)?;
+
+            if staged != b' ' || unstaged != b' ' {
+                status.entries.push(StatusEntry {
+                    path: entry.path.clone(),
+                    staged,
+                    unstaged,
+                    head: head_entry,
+                    index: Some((entry.mode, entry.id)).filter(|_| !entry.intent_to_add()),
+                    work_tree_mode,
+                });
+            }
+        }
+
+        // Whatever HEAD has left is gone from the index
+        for (path, head_entry) in head {
+            if unmerged.contains_key(&path) {
+                continue;
+            }
+            status.entries.push(StatusEntry {
+                path,
+                staged: b'D',
+                unstaged: b' ',
+                head: Some(head_entry),
+                index: None,
+                work_tree_mode: None,
+            });
+        }
+        status.entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+        for conflict in unmerged.values_mut() {
+            conflict.work_tree_mode = self.work_tree_entry(&conflict.path)?.map(|(mode, _)| mode);
+        }
+        status.unmerged = unmerged.into_values().collect();
+
+        if untracked_files != UntrackedFiles::No {
+            let work_tree = self.require_work_tree()?;
+            let mut files: Vec<Vec<u8>> = self
+                .work_tree_files()?
+                .into_iter()
+                .filter(|path| !tracked.contains(path))
+                .collect();
+            // Nested repositories are listed as directories, but a symlink to a
+            // directory is just a symlink
+            for path in &mut files {
+                let full_path = work_tree.join(path_from_bytes(path));
+                if fs::symlink_metadata(full_path).is_ok_and(|meta| meta.file_type().is_dir()) {
+                    path.push(b'/');
+                }
+            }
+            status.untracked = match untracked_files {
+                UntrackedFiles::Normal => collapse_untracked(files, &tracked),
+                _ => files,
+            };
+        }
+
+        Ok(status)
+    }
+}
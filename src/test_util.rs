@@ -0,0 +1,36 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// A scratch directory of files for a test, removed again when the test ends
+pub struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    // Create the directory under the system temp dir with the given files;
+    // `name` must be unique among the tests
+    pub fn new(name: &str, files: &[(&str, &str)]) -> Self {
+        let root = env::temp_dir().join(format!("mygit-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        for (path, content) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        Self(root.canonicalize().unwrap())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}